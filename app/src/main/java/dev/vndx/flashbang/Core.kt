@@ -5,8 +5,8 @@ import android.os.storage.StorageManager
 import android.util.Log
 import kotlinx.coroutines.sync.Mutex
 import kotlinx.coroutines.sync.withLock
-import uniffi.fb_core.CardPage
 import uniffi.fb_core.CardSource
+import uniffi.fb_core.CompileOptions
 import uniffi.fb_core.LoadResult
 import uniffi.fb_core.SourceConfig
 import java.io.File
@@ -14,6 +14,9 @@ import javax.inject.Inject
 import javax.inject.Singleton
 import uniffi.mobile.Core as FFICore
 
+/** Number of cards compiled together on each thread */
+private const val COMPILE_CHUNK_SIZE = 16u
+
 /**
  * Singleton wrapper around ffi World
  */
@@ -31,7 +34,23 @@ class Core @Inject constructor(private val context: Application) {
         val results = core.worldLoadFromGithub(repo, branch, token)
 
         results.errors.forEach {
-             Log.e(TAG, "Error while loading : ${it.error} at ${it.path}")
+            val location = it.span?.let { span -> "${span.file}:${span.line}:${span.column}" }
+            Log.e(TAG, "Error while loading : ${it.error} at ${location ?: it.path}")
+        }
+        results.warnings.forEach {
+            Log.w(TAG, "Skipped directive at ${it.span.file}:${it.span.line}: ${it.message}")
+        }
+
+        results.stale?.let {
+            Log.w(TAG, "Couldn't sync, showing last synced deck (${it.sha}): ${it.error}")
+        }
+        results.diff?.let {
+            Log.i(
+                TAG,
+                "Synced ${it.oldSha} -> ${it.newSha}: ${it.added.size} added, " +
+                    "${it.removed.size} removed, ${it.moved.size} moved, " +
+                    "${it.modified.size} modified"
+            )
         }
 
         // Mark all the newly created directories as groups, so we don't get semi cached packages
@@ -44,11 +63,26 @@ class Core @Inject constructor(private val context: Application) {
 
     fun inspectSource(): String? = core.worldInspectSource()
 
-    suspend fun compileCards(cards: List<CardSource>, config: SourceConfig): List<CardPage> {
+    suspend fun compileCards(cards: List<CardSource>, config: SourceConfig): List<String> {
         return compilationMutex.withLock {
-            core.worldPrepareSource(cards, config)
-            val res = core.worldCompile()
-            res
+            // Only cards missing from the render cache get compiled, and those that fail get an
+            // error placeholder instead of failing everything
+            val options = CompileOptions(
+                COMPILE_CHUNK_SIZE, Runtime.getRuntime().availableProcessors().toUInt()
+            )
+            val res = core.worldRender(cards, config, options)
+
+            res.cards.forEach { card ->
+                card.diagnostics.forEach {
+                    Log.e(TAG, "Card ${card.id} failed to compile: ${it.message}")
+                }
+            }
+
+            res.warnings.forEach {
+                Log.w(TAG, "Typst warning: ${it.message} at ${it.location?.file}:${it.location?.line}")
+            }
+
+            res.cards.flatMap { it.question + it.answer }
         }
     }
 }
\ No newline at end of file
@@ -4,6 +4,8 @@ import dev.vndx.flashbang.ui.LocalDateSerializer
 import kotlinx.serialization.ExperimentalSerializationApi
 import kotlinx.serialization.KSerializer
 import kotlinx.serialization.Serializable
+import kotlinx.serialization.Transient
+import uniffi.fb_core.CardOrigin
 import uniffi.fb_core.CardSource
 import uniffi.fb_core.FuzzyItem
 import java.time.LocalDate
@@ -42,11 +44,20 @@ data class Card(
     val answer: String,
     val header: Header?,
     @Serializable(with = LocalDateSerializer::class) var scheduledFor: LocalDate? = null,
+    @Transient val origin: CardOrigin? = null,
+    val deck: String = "",
+    /** Deletion group hidden by the card, for cards generated from a #cloze */
+    val cloze: UInt? = null,
+    /** Suspended cards are shown but never studied */
+    val suspended: Boolean = false,
+    val authors: List<String> = emptyList(),
 ) : CardSource, FuzzyItem, Item {
     override fun headerContent(): String? = header?.content
 
     override fun id(): String = id
 
+    override fun deck(): String = deck
+
     override fun name(): String = name
 
     override fun question(): String = question
@@ -55,6 +66,10 @@ data class Card(
 
     override fun locations(): List<String> = locations.map { it.fullPath }
 
+    override fun origin(): CardOrigin? = origin
+
+    override fun cloze(): UInt? = cloze
+
     override fun key(): String = name
 
     override fun data(): String = id
@@ -19,7 +19,12 @@ import kotlinx.coroutines.flow.flow
 import kotlinx.coroutines.flow.flowOn
 import kotlinx.coroutines.flow.stateIn
 import uniffi.fb_core.CoreException
+import uniffi.fb_core.DeckDiff
+import uniffi.fb_core.DeckManifest
 import uniffi.fb_core.LoadResult
+import uniffi.fb_core.ResolvedRef
+import uniffi.fb_core.SourceConfig
+import uniffi.fb_core.StaleInfo
 import javax.inject.Inject
 
 data class CardRepositoryDetails(
@@ -30,7 +35,16 @@ data class CardRepositoryDetails(
 
 
 data class CardsData(
-    val cards: Map<String, Card>, val rootTags: List<Tag>
+    val cards: Map<String, Card>,
+    val rootTags: List<Tag>,
+    /** Set when the deck couldn't be synced, and the last synced one is shown instead */
+    val stale: StaleInfo? = null,
+    /** What changed since the previously synced revision of the deck */
+    val diff: DeckDiff? = null,
+    /** What the deck is pinned to, updates are only expected for branches */
+    val reference: ResolvedRef? = null,
+    /** The deck's flashbang.toml, if it has one */
+    val manifest: DeckManifest? = null,
 ) {
     companion object {
         fun fromLoad(core: Core, load: LoadResult): CardsData {
@@ -63,7 +77,13 @@ data class CardsData(
                         Header(
                             headerInfo.content()
                         )
-                    })
+                    },
+                    origin = it.origin,
+                    deck = it.deck,
+                    cloze = it.cloze,
+                    suspended = it.suspended,
+                    authors = it.authors,
+                )
 
                 locations.forEach { tag ->
                     tag.ancestors.forEach { ancestor ->
@@ -77,7 +97,14 @@ data class CardsData(
 
             core.core.fuzzyAddItems(cards)
 
-            return CardsData(cards.associateBy { it.id }, rootTags.toList())
+            return CardsData(
+                cards.associateBy { it.id },
+                rootTags.toList(),
+                load.stale,
+                load.diff,
+                load.reference,
+                load.manifest,
+            )
         }
     }
 }
@@ -128,10 +155,24 @@ sealed interface CardsUiState {
             get() = data.cards
         override val rootTags: List<Tag>
             get() = data.rootTags
+        override val manifest: DeckManifest?
+            get() = data.manifest
     }
 
     data class Failure(val exception: CoreException) : CardsUiState
 
     val cards: Map<String, Card> get() = emptyMap()
     val rootTags: List<Tag> get() = emptyList()
+    val manifest: DeckManifest? get() = null
+}
+
+/** The config with the fields set by the deck's manifest replaced */
+fun SourceConfig.withDeck(manifest: DeckManifest?): SourceConfig {
+    val overrides = manifest?.config ?: return this
+    return SourceConfig(
+        overrides.pageWidth ?: pageWidth,
+        overrides.textSize ?: textSize,
+        overrides.textColor ?: textColor,
+        overrides.sansMath ?: sansMath,
+    )
 }
@@ -80,6 +80,21 @@ data class CardHistoryScreen(val cardId: String) : Screen {
                 style = MaterialTheme.typography.headlineMedium
             )
 
+            if (card.authors.isNotEmpty()) {
+                Text(
+                    text = "By ${card.authors.joinToString(", ")}",
+                    style = MaterialTheme.typography.bodyMedium,
+                    color = MaterialTheme.colorScheme.outline
+                )
+            }
+            if (card.suspended) {
+                Text(
+                    text = "Suspended, this card isn't studied",
+                    style = MaterialTheme.typography.bodyMedium,
+                    color = MaterialTheme.colorScheme.outline
+                )
+            }
+
             Row(
                 modifier = Modifier.fillMaxWidth(),
                 horizontalArrangement = Arrangement.SpaceBetween
@@ -45,6 +45,7 @@ import dev.vndx.flashbang.R
 import dev.vndx.flashbang.TAG
 import dev.vndx.flashbang.ui.CardsUiState
 import dev.vndx.flashbang.ui.CardsViewModel
+import dev.vndx.flashbang.ui.withDeck
 import dev.vndx.flashbang.ui.SettingsViewModel
 import dev.vndx.flashbang.ui.Sizes
 import kotlinx.coroutines.Dispatchers
@@ -161,7 +162,7 @@ class CardPreviewScreen(val cardId: String) : Screen {
             val color = MaterialTheme.colorScheme.onBackground
             val pageWidthPixels = with(density) { maxWidth.toPx() }
             val context = LocalContext.current
-            val pagesFlow = remember(maxWidth, density, preferences, card) {
+            val pagesFlow = remember(maxWidth, density, preferences, card, cardsState.manifest) {
                 flow {
                     Log.w(TAG, "Compiling for $maxWidth")
                     val pages = cardsViewModel.core.compileCards(
@@ -170,9 +171,9 @@ class CardPreviewScreen(val cardId: String) : Screen {
                             preferences.preferences.cardFontSize.toUInt(),
                             ((color.value shr 32) and 0xFFFFFFuL).toUInt(),
                             preferences.preferences.useSansMath
-                        )
-                    ).filterIndexed { index, _ -> index > 0 }.map {
-                        val svg = it.svg()
+                        ).withDeck(cardsState.manifest)
+                    ).map {
+                        val svg = it
                         val request =
                             ImageRequest.Builder(context).data(ByteBuffer.wrap(svg.toByteArray()))
                                 .decoderFactory(
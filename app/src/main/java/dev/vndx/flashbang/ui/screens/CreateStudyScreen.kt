@@ -139,6 +139,7 @@ class CreateStudyScreen : Screen {
                             }.keys
 
                             val cardsToAdd = dueCards.mapNotNull { id -> currentCardsState.cards[id] }
+                                .filter { !it.suspended }
                             cardsToAdd.forEach { selectionViewModel.selectCard(it) }
                         }
                     }) {
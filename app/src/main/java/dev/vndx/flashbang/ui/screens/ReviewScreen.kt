@@ -45,6 +45,7 @@ import dev.vndx.flashbang.TAG
 import dev.vndx.flashbang.domain.Study
 import dev.vndx.flashbang.ui.CardsUiState
 import dev.vndx.flashbang.ui.CardsViewModel
+import dev.vndx.flashbang.ui.withDeck
 import dev.vndx.flashbang.ui.SettingsViewModel
 import dev.vndx.flashbang.ui.Sizes
 import dev.vndx.flashbang.ui.StudiesState
@@ -105,10 +106,11 @@ class ReviewScreen(val study: Study) : Screen {
             return
         }
 
-        val card = cardsState.cards[cards[page / 2]]
+        // Cards suspended since the study was created are skipped
+        val cardSources = cards.mapNotNull { cardsState.cards[it] }.filter { !it.suspended }
+        val card = cardSources.getOrNull(page / 2)
         val onAnswer = page % 2 == 1
 
-        val cardSources = cards.mapNotNull { cardsState.cards[it] }
         val pagesCount = cardSources.size * 2
 
         val ratingButton: @Composable RowScope.(Rating, Int, Color) -> Unit =
@@ -145,7 +147,8 @@ class ReviewScreen(val study: Study) : Screen {
                     initialValue = null,
                     cardSources,
                     maxWidth,
-                    preferences
+                    preferences,
+                    cardsState.manifest
                 ) {
                     if (cardSources.isNotEmpty()) {
                         value = null
@@ -156,10 +159,10 @@ class ReviewScreen(val study: Study) : Screen {
                                     preferences.preferences.cardFontSize.toUInt(),
                                     ((color.value shr 32) and 0xFFFFFFuL).toUInt(),
                                     preferences.preferences.useSansMath
-                                )
-                            ).filterIndexed { index, _ -> index > 0 }.map {
+                                ).withDeck(cardsState.manifest)
+                            ).map {
                                 ImageRequest.Builder(context)
-                                    .data(ByteBuffer.wrap(it.svg().toByteArray())).decoderFactory(
+                                    .data(ByteBuffer.wrap(it.toByteArray())).decoderFactory(
                                         SvgDecoder.Factory()
                                     ).build()
                             }
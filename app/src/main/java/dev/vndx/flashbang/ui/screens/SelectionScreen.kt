@@ -100,12 +100,14 @@ class SelectionScreen(@Transient val _selection: SelectionViewModel? = null) : E
     @Composable
     override fun Directory(tag: Tag, onClick: () -> Unit) {
         val selection = selection
+        // Suspended cards aren't studied, selecting a tag leaves them out
+        val cards = remember(tag) { tag.indirectCards.filter { !it.suspended } }
 
         val state by remember {
             derivedStateOf {
                 when {
-                    tag.indirectCards.all { selection.isSelected(it) } -> ToggleableState.On
-                    tag.indirectCards.none { selection.isSelected(it) } -> ToggleableState.Off
+                    cards.all { selection.isSelected(it) } -> ToggleableState.On
+                    cards.none { selection.isSelected(it) } -> ToggleableState.Off
                     else -> ToggleableState.Indeterminate
                 }
             }
@@ -121,13 +123,13 @@ class SelectionScreen(@Transient val _selection: SelectionViewModel? = null) : E
                     state = state,
                     onClick = {
                         when (state) {
-                            ToggleableState.Off -> tag.indirectCards.forEach {
+                            ToggleableState.Off -> cards.forEach {
                                 selection.selectCard(
                                     it
                                 )
                             }
 
-                            else -> tag.indirectCards.forEach { selection.deselectCard(it) }
+                            else -> cards.forEach { selection.deselectCard(it) }
                         }
                     },
                     modifier = Modifier
@@ -196,6 +196,19 @@ open class SettingsScreen : Screen {
                     onValueChange = { vm.update { setGithubToken(it) } }
                 )
             }
+            item {
+                val cardsViewModel: CardsViewModel =
+                    viewModel(viewModelStoreOwner = LocalActivity.current as ViewModelStoreOwner)
+                val cardsState by cardsViewModel.uiState.collectAsState()
+
+                // Only decks with a flashbang.toml have a name
+                cardsState.manifest?.let { manifest ->
+                    SettingsAction(
+                        title = manifest.name ?: stringResource(R.string.unnamed_deck),
+                        subtitle = manifest.description
+                    )
+                }
+            }
             item {
                 val cardsViewModel: CardsViewModel =
                     viewModel(viewModelStoreOwner = LocalActivity.current as ViewModelStoreOwner)
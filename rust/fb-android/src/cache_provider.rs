@@ -1,45 +1,238 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-use fb_core::cache::CacheProvider;
+use fb_core::{
+    cache::{CacheProvider, CachedDeck, DeckKey, RenderCacheProvider},
+    error::CoreError,
+    render::CachedRender,
+};
+use parking_lot::Mutex;
 
 const SHA_FILE: &str = "sha";
 const TARBALL_FILE: &str = "tarball.tar.gz";
 
+/// Size of the files directly in a directory
+fn dir_size(dir: &Path) -> Result<u64, CoreError> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Stores each deck in its own directory, named after the slug of its key
 pub struct FileSystemCacheProvider {
     cache_path: PathBuf,
-    sha_path: PathBuf,
-    tarball_path: PathBuf,
 }
 
 impl FileSystemCacheProvider {
     pub fn new(cache_path: PathBuf) -> Self {
-        Self {
-            sha_path: cache_path.join(SHA_FILE),
-            tarball_path: cache_path.join(TARBALL_FILE),
-            cache_path,
+        Self { cache_path }
+    }
+
+    fn deck_path(&self, deck: &DeckKey) -> PathBuf {
+        self.cache_path.join(deck.slug())
+    }
+
+    /// Move the files of the single deck cache, from before decks had their own directory,
+    /// to the directory of the first deck that is asked for. That deck is the one that was
+    /// configured when the app was updated, so it can still be loaded while offline.
+    fn adopt_legacy(&self, deck: &DeckKey) -> Result<(), CoreError> {
+        let legacy = self.cache_path.join(TARBALL_FILE);
+        let path = self.deck_path(deck);
+        if !legacy.is_file() || path.exists() {
+            return Ok(());
         }
+
+        log::info!("Moving the cached deck to {path:?}");
+        std::fs::create_dir_all(&path)?;
+        for file in [TARBALL_FILE, SHA_FILE] {
+            match std::fs::rename(self.cache_path.join(file), path.join(file)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl CacheProvider for FileSystemCacheProvider {
-    fn get_sha(&self) -> Result<String, fb_core::error::CoreError> {
-        Ok(std::fs::read_to_string(&self.sha_path)?)
+    fn get_sha(&self, deck: &DeckKey) -> Result<String, CoreError> {
+        self.adopt_legacy(deck)?;
+        let path = self.deck_path(deck).join(SHA_FILE);
+        Ok(std::fs::read_to_string(path)?)
     }
-    fn save_sha(&self, sha: String) -> Result<(), fb_core::error::CoreError> {
-        std::fs::create_dir_all(&self.cache_path)?;
-        std::fs::write(&self.sha_path, sha)?;
+    fn save_sha(&self, deck: &DeckKey, sha: String) -> Result<(), CoreError> {
+        let path = self.deck_path(deck);
+        std::fs::create_dir_all(&path)?;
+        std::fs::write(path.join(SHA_FILE), sha)?;
 
         Ok(())
     }
-    fn get_tarball(&self) -> Result<Box<dyn std::io::Read>, fb_core::error::CoreError> {
-        Ok(Box::new(std::fs::File::open(&self.tarball_path)?))
+    fn get_tarball(&self, deck: &DeckKey) -> Result<Box<dyn std::io::Read>, CoreError> {
+        self.adopt_legacy(deck)?;
+        let path = self.deck_path(deck).join(TARBALL_FILE);
+        Ok(Box::new(std::fs::File::open(path)?))
     }
-    fn save_tarball(&self, data: &mut dyn std::io::Read) -> Result<(), fb_core::error::CoreError> {
-        std::fs::create_dir_all(&self.cache_path)?;
-        let mut dest = std::fs::File::create(&self.tarball_path)?;
+    fn save_tarball(&self, deck: &DeckKey, data: &mut dyn std::io::Read) -> Result<(), CoreError> {
+        let path = self.deck_path(deck);
+        std::fs::create_dir_all(&path)?;
+        let mut dest = std::fs::File::create(path.join(TARBALL_FILE))?;
 
         std::io::copy(data, &mut dest)?;
 
         Ok(())
     }
+    fn list(&self) -> Result<Vec<CachedDeck>, CoreError> {
+        let entries = match std::fs::read_dir(&self.cache_path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut decks = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let Some(key) = entry.file_name().to_str().and_then(DeckKey::from_slug) else {
+                continue;
+            };
+            let path = entry.path();
+
+            decks.push(CachedDeck {
+                sha: std::fs::read_to_string(path.join(SHA_FILE)).ok(),
+                size: dir_size(&path)?,
+                key,
+            });
+        }
+
+        Ok(decks)
+    }
+    fn evict(&self, deck: &DeckKey) -> Result<(), CoreError> {
+        match std::fs::remove_dir_all(self.deck_path(deck)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stores each rendered card in its own directory, one svg file per page. When the cache
+/// grows past its maximum size, the cards that were used the longest time ago are removed.
+pub struct FileSystemRenderCacheProvider {
+    cache_path: PathBuf,
+    max_size: u64,
+    /// Size of the cache in bytes, computed on the first save
+    size: Mutex<Option<u64>>,
+}
+
+impl FileSystemRenderCacheProvider {
+    pub fn new(cache_path: PathBuf, max_size: u64) -> Self {
+        Self {
+            cache_path,
+            max_size,
+            size: Mutex::new(None),
+        }
+    }
+
+    fn read_pages(dir: &Path, side: &str) -> Option<Vec<String>> {
+        let count = std::fs::read_to_string(dir.join(side)).ok()?;
+        (0..count.trim().parse::<usize>().ok()?)
+            .map(|i| std::fs::read_to_string(dir.join(format!("{side}-{i}.svg"))).ok())
+            .collect()
+    }
+
+    fn write_pages(dir: &Path, side: &str, pages: &[String]) -> Result<(), CoreError> {
+        for (i, page) in pages.iter().enumerate() {
+            std::fs::write(dir.join(format!("{side}-{i}.svg")), page)?;
+        }
+        // Written last so that partially saved cards are never read
+        std::fs::write(dir.join(side), pages.len().to_string())?;
+
+        Ok(())
+    }
+
+    /// Mark a card as used now, the modification time of its directory is its last use
+    fn touch(dir: &Path) -> std::io::Result<()> {
+        std::fs::File::open(dir)?.set_modified(SystemTime::now())
+    }
+
+    /// Cached cards with their size and last use, the least recently used first
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>, CoreError> {
+        let dirs = match std::fs::read_dir(&self.cache_path) {
+            Ok(dirs) => dirs,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut entries = Vec::new();
+        for dir in dirs {
+            let dir = dir?;
+            let metadata = dir.metadata()?;
+            if metadata.is_dir() {
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                entries.push((dir.path(), dir_size(&dir.path())?, used));
+            }
+        }
+        entries.sort_by_key(|(_, _, used)| *used);
+
+        Ok(entries)
+    }
+
+    /// Account for `added` bytes, removing the least recently used cards if the cache gets
+    /// too big. A quarter of the cache is freed at once so that this doesn't run on every save.
+    fn reserve(&self, added: u64) -> Result<(), CoreError> {
+        let mut size = self.size.lock();
+        if let Some(size) = size.as_mut() {
+            *size += added;
+            if *size <= self.max_size {
+                return Ok(());
+            }
+        }
+
+        // The estimate is off when cards are saved again, the actual size is used instead
+        let entries = self.entries()?;
+        let mut current = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        if current > self.max_size {
+            for (dir, dir_size, _) in entries {
+                if current <= self.max_size / 4 * 3 {
+                    break;
+                }
+                std::fs::remove_dir_all(dir)?;
+                current -= dir_size;
+            }
+        }
+        *size = Some(current);
+
+        Ok(())
+    }
+}
+
+impl RenderCacheProvider for FileSystemRenderCacheProvider {
+    fn get_render(&self, key: &str) -> Option<CachedRender> {
+        let dir = self.cache_path.join(key);
+        let render = CachedRender {
+            question: Self::read_pages(&dir, "question")?,
+            answer: Self::read_pages(&dir, "answer")?,
+        };
+        if let Err(err) = Self::touch(&dir) {
+            log::warn!("Couldn't mark rendered card {key} as used: {err}");
+        }
+
+        Some(render)
+    }
+    fn save_render(&self, key: &str, render: &CachedRender) -> Result<(), CoreError> {
+        let dir = self.cache_path.join(key);
+        std::fs::create_dir_all(&dir)?;
+
+        Self::write_pages(&dir, "question", &render.question)?;
+        Self::write_pages(&dir, "answer", &render.answer)?;
+
+        self.reserve(dir_size(&dir)?)
+    }
 }
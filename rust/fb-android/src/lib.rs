@@ -2,19 +2,26 @@ use std::{path::PathBuf, sync::Arc};
 
 use android_logger::{Config, FilterBuilder};
 use fb_core::{
+    cache::{CachedDeck, DeckKey},
     cards::{CardSource, CardState, SourceConfig},
     error::CoreError,
     fsrs::MemoryState,
     fuzzy::{FuzzyItem, FuzzyState, FuzzyStatus},
+    remote::RemoteConfig,
+    render::RenderOutput,
     scheduler::{
         Progress, SchedulerItem, SchedulerMemoryState, SchedulerNextState, SchedulerState,
     },
-    world::{CardPage, LoadResult, WorldState},
+    world::{
+        AssetLimits, CompileOptions, CompileOutput, LoadOptions, LoadResult, MergedLoadResult,
+        NamespacedDeck, WorldState,
+    },
 };
 use parking_lot::Mutex;
 
 use crate::{
-    cache_provider::FileSystemCacheProvider, package_provider::DownloadingPackageProvider,
+    cache_provider::{FileSystemCacheProvider, FileSystemRenderCacheProvider},
+    package_provider::DownloadingPackageProvider,
 };
 
 mod cache_provider;
@@ -22,6 +29,9 @@ mod package_provider;
 
 uniffi::setup_scaffolding!();
 
+/// Maximum size of the rendered cards kept on disk, in bytes
+const RENDER_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
 /// Main struct of this library, acts as a global context for the different features this
 /// implements
 #[derive(uniffi::Object)]
@@ -42,9 +52,15 @@ impl Core {
         let package_provider = DownloadingPackageProvider::new(packages_path, cache_groups.clone());
         let cache_provider = FileSystemCacheProvider::new(cache_path);
 
+        let world = WorldState::new(package_provider, cache_provider);
+        world.set_render_cache_provider(FileSystemRenderCacheProvider::new(
+            data_path.join("renders"),
+            RENDER_CACHE_SIZE,
+        ));
+
         Ok(Self {
             card: CardState::new(),
-            world: WorldState::new(package_provider, cache_provider),
+            world,
             fuzzy: FuzzyState::new(),
             scheduler: SchedulerState::new()?,
             cache_groups,
@@ -67,6 +83,29 @@ impl Core {
     ) -> Result<LoadResult, CoreError> {
         self.world.load_from_github(&self.card, repo, branch, token)
     }
+    fn worldLoadFromRemote(
+        &self,
+        config: RemoteConfig,
+        options: LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        self.world
+            .load_from_remote(&self.card, config.remote()?.as_ref(), &options)
+    }
+    fn worldLoadDecks(&self, decks: Vec<NamespacedDeck>) -> Result<MergedLoadResult, CoreError> {
+        self.world.load_decks(&self.card, decks)
+    }
+    fn worldCachedDecks(&self) -> Result<Vec<CachedDeck>, CoreError> {
+        self.world.cached_decks()
+    }
+    fn worldEvictDeck(&self, deck: DeckKey) -> Result<(), CoreError> {
+        self.world.evict_deck(&deck)
+    }
+    fn worldCacheSize(&self) -> Result<u64, CoreError> {
+        self.world.cache_size()
+    }
+    fn worldSetAssetLimits(&self, limits: AssetLimits) {
+        self.world.set_asset_limits(limits)
+    }
     fn worldInspectSource(&self) -> Option<String> {
         self.world.inspect_source()
     }
@@ -77,10 +116,32 @@ impl Core {
     ) -> Result<(), CoreError> {
         self.world.prepare_source(&self.card, cards, config)
     }
-    fn worldCompile(&self) -> Result<Vec<Arc<CardPage>>, CoreError> {
+    fn worldCompile(&self) -> Result<CompileOutput, CoreError> {
+        self.world.compile()
+    }
+    fn worldCompileIsolated(
+        &self,
+        cards: Vec<Arc<dyn CardSource>>,
+        config: SourceConfig,
+    ) -> Result<CompileOutput, CoreError> {
+        self.world.compile_isolated(&self.card, &cards, config)
+    }
+    fn worldCompileParallel(
+        &self,
+        cards: Vec<Arc<dyn CardSource>>,
+        config: SourceConfig,
+        options: CompileOptions,
+    ) -> Result<CompileOutput, CoreError> {
         self.world
-            .compile()
-            .map(|pages| pages.into_iter().map(|p| Arc::new(p)).collect())
+            .compile_parallel(&self.card, &cards, config, options)
+    }
+    fn worldRender(
+        &self,
+        cards: Vec<Arc<dyn CardSource>>,
+        config: SourceConfig,
+        options: CompileOptions,
+    ) -> Result<RenderOutput, CoreError> {
+        self.world.render(&self.card, &cards, config, options)
     }
     fn fuzzyInit(&self, pattern: String) {
         self.fuzzy.init(&pattern);
@@ -1,6 +1,7 @@
 use std::{path::PathBuf, sync::Arc};
 
 use fb_core::{
+    http::HttpClient,
     packages::PackageProvider,
     typst::{
         diag::{FileError, FileResult},
@@ -10,7 +11,6 @@ use fb_core::{
     world::{FileSlot, WorldState},
 };
 use parking_lot::Mutex;
-use reqwest::StatusCode;
 
 /// The default Typst registry.
 pub const DEFAULT_REGISTRY: &str = "https://packages.typst.org";
@@ -20,7 +20,7 @@ pub const DEFAULT_NAMESPACE: &str = "preview";
 
 pub struct DownloadingPackageProvider {
     packages_path: PathBuf,
-    client: reqwest::blocking::Client,
+    client: Arc<HttpClient>,
     cache_groups: Arc<Mutex<Vec<String>>>,
 }
 
@@ -28,7 +28,7 @@ impl DownloadingPackageProvider {
     pub fn new(packages_path: PathBuf, cache_groups: Arc<Mutex<Vec<String>>>) -> Self {
         Self {
             packages_path,
-            client: reqwest::blocking::Client::new(),
+            client: HttpClient::shared(),
             cache_groups,
         }
     }
@@ -76,10 +76,10 @@ impl DownloadingPackageProvider {
         let package_path = self.get_package_directory(spec);
 
         // Make sure the package has been downloaded
-        self.download_package(spec).map_err(|err| FileError::from_io(err, &package_path))?;
+        self.download_package(spec)
+            .map_err(|err| FileError::from_io(err, &package_path))?;
 
-        let path = package_path
-            .join(id.vpath().as_rootless_path());
+        let path = package_path.join(id.vpath().as_rootless_path());
 
         let slot = slot(path)?;
 
@@ -94,7 +94,7 @@ impl DownloadingPackageProvider {
     }
 
     fn package_is_downloaded(&self, spec: &PackageSpec) -> bool {
-        let path  = self.get_package_directory(spec);
+        let path = self.get_package_directory(spec);
 
         std::fs::exists(path).unwrap_or_default()
     }
@@ -115,15 +115,10 @@ impl DownloadingPackageProvider {
         );
         let dir = self.get_package_directory(spec);
 
-        let data = match self.client.get(&url).send() {
-            Ok(data) => match data.bytes() {
-                Ok(data) => data,
-                Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::Other, err)),
-            },
-            Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => {
-                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, err));
-            }
-            Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::Other, err)),
+        let data = match self.client.bytes_if_found(self.client.get(&url)) {
+            Ok(Some(data)) => data,
+            Ok(None) => return Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+            Err(err) => return Err(std::io::Error::other(err)),
         };
 
         std::fs::create_dir_all(&dir)?;
@@ -136,7 +131,9 @@ impl DownloadingPackageProvider {
                 std::io::Error::new(std::io::ErrorKind::Other, err)
             })?;
 
-        self.cache_groups.lock().push(dir.to_string_lossy().to_string());
+        self.cache_groups
+            .lock()
+            .push(dir.to_string_lossy().to_string());
 
         Ok(())
     }
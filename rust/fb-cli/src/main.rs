@@ -1,17 +1,39 @@
-use std::{error::Error, path::PathBuf, time::Instant};
+use std::{collections::BTreeMap, path::PathBuf, time::Instant};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 use fb_core::{
     cards::{CardInfo, CardState},
     error::{AsCoreError, CoreError},
+    world::{LoadOptions, WorldState},
 };
 use serde::Serialize;
-use walkdir::WalkDir;
+
+use crate::package_provider::LocalPackageProvider;
+
+mod package_provider;
+mod pdf;
 
 #[derive(Parser)]
 #[command(author, version, about = "Flashbang CLI - A tool to parse flashcards from Typst files", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    parse: Option<ParseArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Export cards to a pdf laid out for printing
+    Pdf(pdf::PdfArgs),
+}
+
+/// Arguments of the default command, which parses cards to json
+#[derive(clap::Args)]
+struct ParseArgs {
     /// Directory to search for .typ files
     #[arg(short, long)]
     search_path: PathBuf,
@@ -27,6 +49,15 @@ struct Cli {
     /// Paths to exclude from searching
     #[arg(short, long, action = clap::ArgAction::Append)]
     exclude: Vec<PathBuf>,
+
+    /// Subdirectory of the search path to look for cards in
+    #[arg(short, long, default_value = "")]
+    root: String,
+
+    /// Globs of the paths outside of the root that INCLUDE files are still copied from,
+    /// relative to the search path
+    #[arg(long, action = clap::ArgAction::Append)]
+    external: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -37,6 +68,9 @@ struct Card {
     pub header: Option<String>,
     pub question: String,
     pub answer: String,
+    pub suspended: bool,
+    pub authors: Vec<String>,
+    pub cloze: Option<u32>,
 }
 
 impl From<CardInfo> for Card {
@@ -48,6 +82,9 @@ impl From<CardInfo> for Card {
             header: value.header.map(|h| h.inner.clone()),
             question: value.question,
             answer: value.answer,
+            suspended: value.suspended,
+            authors: value.authors,
+            cloze: value.cloze,
         }
     }
 }
@@ -61,6 +98,14 @@ fn main() -> Result<(), CoreError> {
     pretty_env_logger::init();
 
     let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Pdf(args)) => pdf::run(args),
+        // Without a subcommand, clap requires the arguments of the default one
+        None => parse(cli.parse.expect("parse arguments are required")),
+    }
+}
+
+fn parse(cli: ParseArgs) -> Result<(), CoreError> {
     let start_time = Instant::now();
 
     let search_path = cli
@@ -71,19 +116,43 @@ fn main() -> Result<(), CoreError> {
     std::fs::create_dir_all(&output_dir).context(Some("Output Dir Creation"))?;
     let output_dir_canonical = output_dir.canonicalize().context(Some("Output Dir"))?;
 
-    let mut excluded_paths = cli
-        .exclude
-        .iter()
-        .filter_map(|path| path.canonicalize().ok())
-        .collect::<Vec<_>>();
-
-    excluded_paths.push(output_dir_canonical.clone());
+    let mut options = LoadOptions {
+        root: cli.root,
+        external: cli.external,
+        ..Default::default()
+    };
+    // Excluded paths, relative to the search path and escaped as they are matched as globs
+    options.exclude.extend(
+        cli.exclude
+            .iter()
+            .chain([&output_dir_canonical])
+            .filter_map(|path| path.canonicalize().ok())
+            .filter_map(|path| {
+                let relative = path.strip_prefix(&search_path).ok()?;
+                Some(globset::escape(&relative.to_string_lossy()))
+            }),
+    );
 
     // Print summary of arguments
     println!("{}", "Flashbang CLI Config".bold());
-    println!("{:>12}: {}", "Search Path", search_path.display().to_string().cyan());
-    println!("{:>12}: {}", "Output JSON", cli.output_file.display().to_string().cyan());
-    println!("{:>12}: {}", "Asset Dir", output_dir_canonical.display().to_string().cyan());
+    println!(
+        "{:>12}: {}",
+        "Search Path",
+        search_path.display().to_string().cyan()
+    );
+    if !options.root.is_empty() {
+        println!("{:>12}: {}", "Deck Root", options.root.cyan());
+    }
+    println!(
+        "{:>12}: {}",
+        "Output JSON",
+        cli.output_file.display().to_string().cyan()
+    );
+    println!(
+        "{:>12}: {}",
+        "Asset Dir",
+        output_dir_canonical.display().to_string().cyan()
+    );
     if !cli.exclude.is_empty() {
         println!("{:>12}:", "Excluded");
         for path in &cli.exclude {
@@ -93,115 +162,88 @@ fn main() -> Result<(), CoreError> {
     println!();
 
     let card_state = CardState::new();
-    let mut errors = Vec::<Box<dyn Error>>::new();
-    let mut cards = Vec::new();
-    let mut asset_count = 0;
-    let mut excluded_count = 0;
-    let mut card_file_count = 0;
-
-    for (id, entry) in WalkDir::new(&search_path)
-        .into_iter()
-        .filter_entry(|entry| {
-            let Ok(can) = entry.path().canonicalize() else {
-                return false;
-            };
-
-            !excluded_paths.contains(&can)
-        })
-        .enumerate()
-    {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(err) => {
-                errors.push(Box::new(err));
-                continue;
-            }
-        };
-
-        let path = entry.path();
-        if path.extension().and_then(|s| s.to_str()) != Some("typ") {
-            continue;
+    let world = WorldState::without_cache(LocalPackageProvider::new());
+    let loaded = world.load_from_directory(&card_state, &search_path, &options)?;
+
+    if let Some(manifest) = &loaded.manifest {
+        let name = manifest.name.as_deref().unwrap_or("Unnamed deck");
+        match &manifest.version {
+            Some(version) => println!("{} {}", name.bold(), version.cyan()),
+            None => println!("{}", name.bold()),
         }
-
-        let relative_path = path
-            .strip_prefix(&search_path)
-            .unwrap_or(path)
-            .display()
-            .to_string();
-
-        let content = match std::fs::read_to_string(path) {
-            Ok(content) => content,
-            Err(err) => {
-                errors.push(Box::new(err));
-                continue;
-            }
-        };
-
-        if content.starts_with("//![FLASHBANG IGNORE]") {
-            println!("{:>10} {}", "EXCLUDED".yellow().bold(), relative_path);
-            excluded_count += 1;
-            continue;
+        if let Some(description) = &manifest.description {
+            println!("{description}");
         }
+        println!();
+    }
 
-        if content.starts_with("//![FLASHBANG INCLUDE]") {
-            let absolute_path = entry.path().canonicalize()?;
-            let rel_to_search = absolute_path
-                .strip_prefix(&search_path)
-                .context(Some("Strip Prefix"))?;
-            let new_path = output_dir_canonical.join(rel_to_search);
-
-            if let Some(parent) = new_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            std::fs::write(new_path, content)?;
-            println!("{:>10} {}", "ASSET".blue().bold(), relative_path);
-            asset_count += 1;
-            continue;
+    for warning in &loaded.warnings {
+        println!("{:>10} {}", "WARNING".yellow().bold(), warning);
+    }
+
+    for include in &loaded.includes {
+        let new_path = output_dir_canonical.join(include);
+        if let Some(parent) = new_path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        std::fs::copy(search_path.join(include), new_path)?;
+        println!("{:>10} {}", "ASSET".blue().bold(), include);
+    }
+
+    let mut files = BTreeMap::<&str, usize>::new();
+    for card in &loaded.cards {
+        *files.entry(&card.origin.question.file).or_default() += 1;
+    }
+    for (file, count) in &files {
+        println!("{:>10} {} ({} cards)", "CARDS".green().bold(), file, count);
+    }
 
-        log::debug!("Parsing {:?}", entry.path());
-
-        match card_state.parse(id as u64, &content) {
-            Ok(new_cards) => {
-                if !new_cards.is_empty() {
-                    println!(
-                        "{:>10} {} ({} cards)",
-                        "CARDS".green().bold(),
-                        relative_path,
-                        new_cards.len()
-                    );
-                    cards.extend(new_cards.into_iter().map(Card::from));
-                    card_file_count += 1;
-                } else {
-                    // Maybe it's a helper file or empty?
-                    println!("{:>10} {}", "EMPTY".truecolor(128, 128, 128), relative_path);
-                }
-            }
-            Err(err) => {
-                println!("{:>10} {}", "ERROR".red().bold(), relative_path);
-                errors.push(Box::new(err));
-            }
+    for error in &loaded.errors {
+        println!("{:>10} {}", "ERROR".red().bold(), error.path());
+        if let Some(span) = error.span()
+            && let Ok(content) = std::fs::read_to_string(search_path.join(&span.file))
+            && let Some(line) = content.lines().nth(span.line as usize - 1)
+        {
+            println!("{:>10} {} | {}", "", span.line.to_string().blue(), line);
         }
     }
 
-    if !errors.is_empty() {
+    if !loaded.errors.is_empty() {
         println!("\n{}", "Errors encountered:".red().bold());
-        for error in &errors {
+        for error in &loaded.errors {
             println!("  {} {}", "•".red(), error);
         }
     }
 
+    let file_count = files.len();
+    let cards = loaded.cards.into_iter().map(Card::from).collect::<Vec<_>>();
     let json_string = serde_json::to_string(&cards).context(Some("Serialization"))?;
     std::fs::write(&cli.output_file, json_string).context(Some("Writing JSON"))?;
 
     let duration = start_time.elapsed();
 
     println!("\n{}", "Summary".bold());
-    println!("{:>15} {}", "Total Cards:", cards.len().to_string().green().bold());
-    println!("{:>15} {}", "Files w/ Cards:", card_file_count);
-    println!("{:>15} {}", "Assets Copied:", asset_count.to_string().blue());
-    println!("{:>15} {}", "Files Ignored:", excluded_count.to_string().yellow());
-    println!("{:>15} {}", "Errors:", errors.len().to_string().red());
+    println!(
+        "{:>15} {}",
+        "Total Cards:",
+        cards.len().to_string().green().bold()
+    );
+    println!("{:>15} {}", "Files w/ Cards:", file_count);
+    println!(
+        "{:>15} {}",
+        "Assets Copied:",
+        loaded.includes.len().to_string().blue()
+    );
+    println!(
+        "{:>15} {}",
+        "Warnings:",
+        loaded.warnings.len().to_string().yellow()
+    );
+    println!(
+        "{:>15} {}",
+        "Errors:",
+        loaded.errors.len().to_string().red()
+    );
     println!("{:>15} {:.2?}", "Time Taken:", duration);
 
     Ok(())
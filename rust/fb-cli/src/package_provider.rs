@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use fb_core::{
+    packages::PackageProvider,
+    typst::{
+        diag::{FileError, FileResult, PackageError},
+        foundations::Bytes,
+        syntax::{FileId, Source},
+    },
+    world::{FileSlot, WorldState},
+};
+
+/// Provides packages from the local typst package directories: the ones the typst cli downloads
+/// packages to, and the one for local packages. Packages are never downloaded.
+pub struct LocalPackageProvider {
+    roots: Vec<PathBuf>,
+}
+
+impl LocalPackageProvider {
+    pub fn new() -> Self {
+        Self {
+            roots: [dirs::data_dir(), dirs::cache_dir()]
+                .into_iter()
+                .flatten()
+                .map(|dir| dir.join("typst/packages"))
+                .collect(),
+        }
+    }
+}
+
+impl PackageProvider for LocalPackageProvider {
+    fn get_package_source(&self, id: FileId, world: &WorldState) -> FileResult<Source> {
+        self.load_file(id, world, |path| {
+            let content =
+                std::fs::read_to_string(&path).map_err(|err| FileError::from_io(err, &path))?;
+
+            Ok(FileSlot::with_source(id, Source::new(id, content)))
+        })?
+        .source()
+    }
+
+    fn get_package_file(&self, id: FileId, world: &WorldState) -> FileResult<Bytes> {
+        self.load_file(id, world, |path| {
+            let bytes = std::fs::read(&path).map_err(|err| FileError::from_io(err, &path))?;
+
+            Ok(FileSlot::with_bytes(id, Bytes::new(bytes)))
+        })?
+        .bytes()
+    }
+}
+
+impl LocalPackageProvider {
+    /// Loads a package's file into the world state if not already loaded
+    fn load_file(
+        &self,
+        id: FileId,
+        world: &WorldState,
+        slot: impl FnOnce(PathBuf) -> FileResult<FileSlot>,
+    ) -> FileResult<FileSlot> {
+        if let Some(slot) = world.get_file(&id) {
+            return Ok(slot);
+        }
+
+        let spec = id
+            .package()
+            .expect("PackageProvider can't provide files that aren't from packages");
+
+        let package_path = self
+            .roots
+            .iter()
+            .map(|root| root.join(format!("{}/{}/{}", spec.namespace, spec.name, spec.version)))
+            .find(|path| path.is_dir())
+            .ok_or_else(|| FileError::Package(PackageError::NotFound(spec.clone())))?;
+
+        let slot = slot(package_path.join(id.vpath().as_rootless_path()))?;
+
+        world.load_file(slot.clone());
+
+        Ok(slot)
+    }
+}
@@ -0,0 +1,196 @@
+use std::{path::PathBuf, time::Instant};
+
+use clap::{Args, ValueEnum};
+use colored::Colorize;
+use fb_core::{
+    cards::{CardState, SourceConfig},
+    error::{AsCoreError, CoreError},
+    pdf::{PdfLayout, PdfOptions, export},
+    world::{CompileOptions, LoadOptions, WorldState},
+};
+
+use crate::package_provider::LocalPackageProvider;
+
+#[derive(Args)]
+pub struct PdfArgs {
+    /// Directory of the deck
+    #[arg(short, long)]
+    search_path: PathBuf,
+
+    /// Output pdf file
+    #[arg(short, long)]
+    output_file: PathBuf,
+
+    /// Globs of the paths to exclude, relative to the deck directory
+    #[arg(short, long, action = clap::ArgAction::Append)]
+    exclude: Vec<String>,
+
+    /// Subdirectory of the deck directory to look for cards in
+    #[arg(short, long, default_value = "")]
+    root: String,
+
+    /// Globs of the paths outside of the root that INCLUDE files and assets are loaded from
+    #[arg(long, action = clap::ArgAction::Append)]
+    external: Vec<String>,
+
+    /// Ids of the cards to export, every card when none is given
+    #[arg(short, long = "card", action = clap::ArgAction::Append)]
+    cards: Vec<String>,
+
+    /// How cards are laid out on the pages
+    #[arg(short, long, value_enum, default_value_t = Layout::OnePerPage)]
+    layout: Layout,
+
+    /// Columns of the grid layout
+    #[arg(long, default_value_t = 2)]
+    columns: u32,
+
+    /// Rows of the grid layout
+    #[arg(long, default_value_t = 4)]
+    rows: u32,
+
+    /// Paper size
+    #[arg(short, long, value_enum, default_value_t = Paper::A4)]
+    paper: Paper,
+
+    /// Width of the cards in pt (they are scaled down if they don't fit), 300 unless the deck
+    /// manifest sets it
+    #[arg(long)]
+    card_width: Option<u32>,
+
+    /// Text size in pt, 12 unless the deck manifest sets it
+    #[arg(long)]
+    text_size: Option<u32>,
+
+    /// Number of threads used to compile the cards, all cores by default
+    #[arg(short, long)]
+    threads: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Layout {
+    /// One card per page, the question above the answer
+    OnePerPage,
+    /// Questions on left pages, answers on the right pages facing them
+    Facing,
+    /// Grid of cards with cut lines, answers on the back of the sheets (duplex)
+    Grid,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Paper {
+    A4,
+    A5,
+    Letter,
+}
+
+impl Paper {
+    /// Width and height in pt
+    fn size(self) -> (f64, f64) {
+        match self {
+            Self::A4 => (595.28, 841.89),
+            Self::A5 => (419.53, 595.28),
+            Self::Letter => (612.0, 792.0),
+        }
+    }
+}
+
+pub fn run(args: PdfArgs) -> Result<(), CoreError> {
+    let start_time = Instant::now();
+
+    let card_state = CardState::new();
+    let world = WorldState::without_cache(LocalPackageProvider::new());
+
+    let mut load_options = LoadOptions {
+        root: args.root,
+        external: args.external,
+        ..Default::default()
+    };
+    load_options.exclude.extend(args.exclude);
+    let loaded = world.load_from_directory(&card_state, &args.search_path, &load_options)?;
+
+    for error in &loaded.errors {
+        println!("{:>10} {}", "ERROR".red().bold(), error);
+    }
+    for warning in &loaded.warnings {
+        println!("{:>10} {}", "WARNING".yellow().bold(), warning);
+    }
+
+    let mut cards = loaded.cards;
+
+    if !args.cards.is_empty() {
+        for id in &args.cards {
+            if !cards.iter().any(|card| &card.id == id) {
+                println!("{:>10} no card with id {}", "WARNING".yellow().bold(), id);
+            }
+        }
+        cards.retain(|card| args.cards.contains(&card.id));
+    }
+
+    let mut config = SourceConfig {
+        page_width: 300,
+        text_size: 12,
+        text_color: 0x000000,
+        sans_math: false,
+    };
+    if let Some(manifest) = &loaded.manifest {
+        config = manifest.config.apply(config);
+    }
+    config.page_width = args.card_width.unwrap_or(config.page_width);
+    config.text_size = args.text_size.unwrap_or(config.text_size);
+    let mut compile_options = CompileOptions::default();
+    if let Some(threads) = args.threads {
+        compile_options.threads = threads;
+    }
+
+    let output = world.compile_parallel(&card_state, &cards, config, compile_options)?;
+
+    for card in &output.cards {
+        for diagnostic in &card.diagnostics {
+            println!("{:>10} {} ({})", "ERROR".red().bold(), diagnostic, card.id);
+        }
+    }
+    for warning in &output.warnings {
+        println!("{:>10} {}", "WARNING".yellow().bold(), warning);
+    }
+
+    let (paper_width, paper_height) = args.paper.size();
+    let options = PdfOptions {
+        layout: match args.layout {
+            Layout::OnePerPage => PdfLayout::OnePerPage,
+            Layout::Facing => PdfLayout::Facing,
+            Layout::Grid => PdfLayout::Grid {
+                columns: args.columns,
+                rows: args.rows,
+            },
+        },
+        paper_width,
+        paper_height,
+        ..Default::default()
+    };
+
+    let pdf = export(&output.cards, &options)?;
+    std::fs::write(&args.output_file, pdf).context(Some("Writing PDF"))?;
+
+    let failed = output
+        .cards
+        .iter()
+        .filter(|card| !card.diagnostics.is_empty())
+        .count();
+
+    println!("\n{}", "Summary".bold());
+    println!(
+        "{:>15} {}",
+        "Cards:",
+        output.cards.len().to_string().green().bold()
+    );
+    println!("{:>15} {}", "Failed:", failed.to_string().red());
+    println!(
+        "{:>15} {}",
+        "Output:",
+        args.output_file.display().to_string().cyan()
+    );
+    println!("{:>15} {:.2?}", "Time Taken:", start_time.elapsed());
+
+    Ok(())
+}
@@ -1,14 +1,189 @@
 use std::io::Read;
 
-#[cfg(feature = "github")]
 use crate::error::CoreError;
+#[cfg(feature = "compile")]
+use crate::render::CachedRender;
 
+/// Identifies a deck in the cache: where it is hosted, which repository and which ref
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct DeckKey {
+    /// Host of the repository (`github.com`, ...)
+    pub host: String,
+    /// Repository (`owner/repo`)
+    pub repo: String,
+    /// Branch, tag or commit
+    pub reference: String,
+}
+
+/// A deck stored in the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CachedDeck {
+    pub key: DeckKey,
+    /// Sha of the cached revision, if known
+    pub sha: Option<String>,
+    /// Size taken by the deck in the cache, in bytes
+    pub size: u64,
+}
+
+impl DeckKey {
+    pub fn new(
+        host: impl Into<String>,
+        repo: impl Into<String>,
+        reference: impl Into<String>,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            repo: repo.into(),
+            reference: reference.into(),
+        }
+    }
+
+    pub fn github(repo: impl Into<String>, branch: impl Into<String>) -> Self {
+        Self::new("github.com", repo, branch)
+    }
+
+    /// Name unique to this key that is safe to use as a file name. Every character other than
+    /// ascii alphanumerics, `.` and `-` is escaped as `_` followed by its bytes in hex, and the
+    /// parts are separated by `+`.
+    pub fn slug(&self) -> String {
+        fn escape(part: &str) -> String {
+            let mut escaped = String::with_capacity(part.len());
+            for byte in part.bytes() {
+                match byte {
+                    b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' => {
+                        escaped.push(byte as char)
+                    }
+                    _ => escaped.push_str(&format!("_{byte:02x}")),
+                }
+            }
+            escaped
+        }
+
+        [&self.host, &self.repo, &self.reference]
+            .map(|part| escape(part))
+            .join("+")
+    }
+
+    /// Key of a slug made with [`Self::slug`], None if it isn't one
+    pub fn from_slug(slug: &str) -> Option<Self> {
+        fn unescape(part: &str) -> Option<String> {
+            let mut bytes = Vec::with_capacity(part.len());
+            let mut iter = part.bytes();
+            while let Some(byte) = iter.next() {
+                if byte == b'_' {
+                    let hex = [iter.next()?, iter.next()?];
+                    bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+                } else {
+                    bytes.push(byte);
+                }
+            }
+            String::from_utf8(bytes).ok()
+        }
+
+        let mut parts = slug.split('+');
+        let key = Self::new(
+            unescape(parts.next()?)?,
+            unescape(parts.next()?)?,
+            unescape(parts.next()?)?,
+        );
+
+        parts.next().is_none().then_some(key)
+    }
+}
+
+/// Storage for decks, each deck is identified by a [`DeckKey`] so that several of them can be
+/// cached side by side.
 pub trait CacheProvider: Send + Sync + 'static {
     #[cfg(feature = "github")]
-    fn get_sha(&self) -> Result<String, CoreError>;
+    fn get_sha(&self, deck: &DeckKey) -> Result<String, CoreError>;
+    #[cfg(feature = "github")]
+    fn save_sha(&self, deck: &DeckKey, sha: String) -> Result<(), CoreError>;
+
+    fn get_tarball(&self, deck: &DeckKey) -> Result<Box<dyn Read>, CoreError>;
+    fn save_tarball(&self, deck: &DeckKey, data: &mut dyn Read) -> Result<(), CoreError>;
+
+    /// Every deck in the cache
+    fn list(&self) -> Result<Vec<CachedDeck>, CoreError>;
+    /// Remove a deck from the cache, does nothing if it isn't cached
+    fn evict(&self, deck: &DeckKey) -> Result<(), CoreError>;
+
+    /// Size taken by all the decks in the cache, in bytes
+    fn size(&self) -> Result<u64, CoreError> {
+        Ok(self.list()?.iter().map(|deck| deck.size).sum())
+    }
+}
+
+/// Cache that doesn't store anything, decks are downloaded every time
+pub struct NoCache;
+
+impl CacheProvider for NoCache {
+    #[cfg(feature = "github")]
+    fn get_sha(&self, _deck: &DeckKey) -> Result<String, CoreError> {
+        Err(CoreError::Other {
+            details: "no cache".to_owned(),
+        })
+    }
     #[cfg(feature = "github")]
-    fn save_sha(&self, sha: String) -> Result<(), CoreError>;
+    fn save_sha(&self, _deck: &DeckKey, _sha: String) -> Result<(), CoreError> {
+        Ok(())
+    }
+    fn get_tarball(&self, _deck: &DeckKey) -> Result<Box<dyn Read>, CoreError> {
+        Err(CoreError::Other {
+            details: "no cache".to_owned(),
+        })
+    }
+    fn save_tarball(&self, _deck: &DeckKey, _data: &mut dyn Read) -> Result<(), CoreError> {
+        Ok(())
+    }
+    fn list(&self) -> Result<Vec<CachedDeck>, CoreError> {
+        Ok(Vec::new())
+    }
+    fn evict(&self, _deck: &DeckKey) -> Result<(), CoreError> {
+        Ok(())
+    }
+}
+
+/// Persistent storage for rendered cards, keyed by a hash of everything that affects the
+/// rendering of a card.
+#[cfg(feature = "compile")]
+pub trait RenderCacheProvider: Send + Sync + 'static {
+    /// Get a rendered card, None if it isn't cached (or the cache is broken)
+    fn get_render(&self, key: &str) -> Option<CachedRender>;
+    fn save_render(&self, key: &str, render: &CachedRender) -> Result<(), CoreError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deck_key_slug() {
+        let keys = [
+            DeckKey::github("owner/repo", "main"),
+            DeckKey::new("gitlab.example.com:8080", "group/sub/repo", "feature/ü+_x"),
+            DeckKey::new("", "", ""),
+        ];
+
+        for key in &keys {
+            let slug = key.slug();
+            assert!(
+                slug.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"._-+".contains(&b)),
+                "{slug}"
+            );
+            assert_eq!(DeckKey::from_slug(&slug).as_ref(), Some(key));
+        }
 
-    fn get_tarball(&self) -> Result<Box<dyn Read>, CoreError>;
-    fn save_tarball(&self, data: &mut dyn Read) -> Result<(), CoreError>;
+        assert_eq!(keys[0].slug(), "github.com+owner_2frepo+main");
+        // Distinct keys never share a slug, even when they only differ by separators
+        assert_ne!(
+            DeckKey::new("a", "b_2fc", "d").slug(),
+            DeckKey::new("a", "b/c", "d").slug()
+        );
+        assert_eq!(DeckKey::from_slug("sha"), None);
+        assert_eq!(DeckKey::from_slug("a+b+c+d"), None);
+        assert_eq!(DeckKey::from_slug("a+b_z+c"), None);
+    }
 }
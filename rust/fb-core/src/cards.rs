@@ -3,12 +3,21 @@
 //!  - splitting
 //!  - building typst source files
 
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 use std::sync::Arc;
 
 use itertools::Itertools;
-
-use crate::error::CoreError;
+use typst_syntax::{
+    LinkedNode, SyntaxKind, SyntaxNode,
+    ast::{self, AstNode},
+};
+
+use crate::{
+    codegen::{self, SourceBuilder},
+    directives::{self, Directives},
+    error::{CoreError, SourceSpan},
+    source_map::SourceMap,
+};
 
 #[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct HeaderInfoInner {
@@ -25,21 +34,47 @@ pub struct HeaderInfo(Arc<HeaderInfoInner>);
 #[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct CardInfo {
     pub id: String,
+    /// Namespace of the deck the card comes from, empty for the default deck
+    pub deck: String,
     pub name: String,
     pub locations: Vec<String>,
     pub header: Option<HeaderInfo>,
     pub question: String,
     pub answer: String,
+    pub origin: CardOrigin,
+    /// Set by a `SUSPEND` directive, the card isn't meant to be studied
+    pub suspended: bool,
+    /// Set by `AUTHOR` directives
+    pub authors: Vec<String>,
+    /// Deletion group hidden by the card, if it was generated from a `#cloze`
+    pub cloze: Option<u32>,
+}
+
+/// Where the parts of a card are in the file it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CardOrigin {
+    /// The `#card(...)` call
+    pub header: SourceSpan,
+    pub question: SourceSpan,
+    pub answer: SourceSpan,
 }
 
 #[cfg_attr(feature = "uniffi", uniffi::export(with_foreign))]
 pub trait CardSource: Send + Sync {
     fn header_content(&self) -> Option<String>;
     fn id(&self) -> String;
+    /// Namespace of the deck the card comes from, empty for the default deck. The imports of
+    /// the card are resolved against the files of that deck.
+    fn deck(&self) -> String;
     fn name(&self) -> String;
     fn question(&self) -> String;
     fn answer(&self) -> String;
     fn locations(&self) -> Vec<String>;
+    /// Where the card comes from, if known. Used to point errors at the deck files.
+    fn origin(&self) -> Option<CardOrigin>;
+    /// Deletion group hidden by the card, if it was generated from a `#cloze`
+    fn cloze(&self) -> Option<u32>;
 }
 
 impl<T: CardSource + ?Sized> CardSource for Arc<T> {
@@ -49,6 +84,39 @@ impl<T: CardSource + ?Sized> CardSource for Arc<T> {
     fn id(&self) -> String {
         (**self).id()
     }
+    fn deck(&self) -> String {
+        (**self).deck()
+    }
+    fn name(&self) -> String {
+        (**self).name()
+    }
+    fn question(&self) -> String {
+        (**self).question()
+    }
+    fn answer(&self) -> String {
+        (**self).answer()
+    }
+    fn locations(&self) -> Vec<String> {
+        (**self).locations()
+    }
+    fn origin(&self) -> Option<CardOrigin> {
+        (**self).origin()
+    }
+    fn cloze(&self) -> Option<u32> {
+        (**self).cloze()
+    }
+}
+
+impl<T: CardSource + ?Sized> CardSource for &T {
+    fn header_content(&self) -> Option<String> {
+        (**self).header_content()
+    }
+    fn id(&self) -> String {
+        (**self).id()
+    }
+    fn deck(&self) -> String {
+        (**self).deck()
+    }
     fn name(&self) -> String {
         (**self).name()
     }
@@ -61,6 +129,51 @@ impl<T: CardSource + ?Sized> CardSource for Arc<T> {
     fn locations(&self) -> Vec<String> {
         (**self).locations()
     }
+    fn origin(&self) -> Option<CardOrigin> {
+        (**self).origin()
+    }
+    fn cloze(&self) -> Option<u32> {
+        (**self).cloze()
+    }
+}
+
+impl CardSource for CardInfo {
+    fn header_content(&self) -> Option<String> {
+        self.header.as_ref().map(|header| header.inner.clone())
+    }
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    fn deck(&self) -> String {
+        self.deck.clone()
+    }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn question(&self) -> String {
+        self.question.clone()
+    }
+    fn answer(&self) -> String {
+        self.answer.clone()
+    }
+    fn locations(&self) -> Vec<String> {
+        self.locations.clone()
+    }
+    fn origin(&self) -> Option<CardOrigin> {
+        Some(self.origin.clone())
+    }
+    fn cloze(&self) -> Option<u32> {
+        self.cloze
+    }
+}
+
+/// Id of a card scoped to its deck, cards of the default deck keep their id
+pub fn qualified_id(deck: &str, id: &str) -> String {
+    if deck.is_empty() {
+        id.to_owned()
+    } else {
+        format!("{deck}:{id}")
+    }
 }
 
 impl HeaderInfo {
@@ -97,7 +210,7 @@ impl PartialEq for HeaderInfo {
 }
 
 /// Config for things that the source needs to compile
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Hash)]
 #[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 #[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
 pub struct SourceConfig {
@@ -123,6 +236,9 @@ impl SourceConfig {
     }
 }
 
+/// Typst definitions used by the generated source
+pub(crate) const CARDS_INTERNAL: &str = include_str!("./cards_internal.typ");
+
 pub struct CardState {}
 
 impl CardState {
@@ -132,27 +248,32 @@ impl CardState {
 }
 
 impl CardState {
-    /// Build the source for a set of cards and a config
+    /// Build the source for a set of cards and a config, along with its source map
     pub fn build_source<C: CardSource>(
         &self,
         cards: impl IntoIterator<Item = C>,
         config: SourceConfig,
-    ) -> Result<String, CoreError> {
-        const CARDS_INTERNAL: &'static str = include_str!("./cards_internal.typ");
-
+    ) -> Result<(String, SourceMap), CoreError> {
         use std::io::Write;
 
-        let mut w = Vec::new();
+        let mut w = SourceBuilder::new();
         let mut last_header = None;
 
         writeln!(&mut w, "{CARDS_INTERNAL}")?;
         writeln!(&mut w, "#set page(width: {}pt)", config.page_width)?;
-        writeln!(&mut w, "#let _colors = (text: rgb(\"#{:06X}\"))", config.text_color)?;
+        writeln!(
+            &mut w,
+            "#let _colors = (text: rgb(\"#{:06X}\"))",
+            config.text_color
+        )?;
         writeln!(&mut w, "#let _sizes = (text: {}pt)", config.text_size)?;
         writeln!(&mut w, "#set text(size: _sizes.text, fill: _colors.text)")?;
 
         if config.sans_math {
-            writeln!(&mut w, "#show math.equation: set text(font: \"Noto Sans Math\")")?;
+            writeln!(
+                &mut w,
+                "#show math.equation: set text(font: \"Noto Sans Math\")"
+            )?;
         }
 
         writeln!(&mut w, "#[")?;
@@ -166,117 +287,676 @@ impl CardState {
                 if let Some(header) = (&current_header).as_ref() {
                     writeln!(&mut w, "{}", header)?;
                 }
-
             }
 
-            write!(&mut w, "#card(\"{}\", \"{}\", (", card.id(), card.name())?;
-            for full_path in card.locations() {
-                write!(&mut w, "\"{full_path}\",")?;
-            }
-            writeln!(&mut w, "))")?;
-
-            write!(&mut w, "{}", card.question())?;
+            let id = card.id();
+            let origin = card.origin();
+
+            w.push_mapped(
+                &codegen::card_header(&card)?,
+                &id,
+                origin.as_ref().map(|o| &o.header),
+                false,
+            );
+            writeln!(&mut w)?;
+
+            w.push_mapped(
+                &card.question(),
+                &id,
+                origin.as_ref().map(|o| &o.question),
+                true,
+            );
+            w.ensure_newline();
             writeln!(&mut w, "#answer")?;
-            write!(&mut w, "{}", card.answer())?;
+            w.push_mapped(
+                &card.answer(),
+                &id,
+                origin.as_ref().map(|o| &o.answer),
+                true,
+            );
+            w.ensure_newline();
 
             last_header = current_header;
         }
 
         writeln!(&mut w, "]")?;
 
-        Ok(String::from_utf8(w).map_err(|_| {
-            std::io::Error::new(std::io::ErrorKind::Other, "Couldn't parse bytes to string")
-        })?)
+        w.finish()
     }
 
-    /// Parse a typst source file for the cards inside
-    pub fn parse<'a>(&self, id: u64, content: &'a str) -> Result<Vec<CardInfo>, CoreError> {
-        if content.starts_with("//![FLASHBANG IGNORE]")
-            || content.starts_with("//![FLASHBANG INCLUDE]")
-        {
+    /// Parse a typst source file for the cards inside, path is only used for error reporting.
+    /// Warnings about the directives of the file are logged.
+    pub fn parse(&self, id: u64, path: &str, content: &str) -> Result<Vec<CardInfo>, CoreError> {
+        let (directives, warnings) = Directives::parse(path, content);
+        for warning in warnings {
+            log::warn!("{warning}");
+        }
+
+        self.parse_with(id, path, content, &directives)
+    }
+
+    /// Like [`Self::parse`], given the directives of the file
+    pub fn parse_with(
+        &self,
+        id: u64,
+        path: &str,
+        content: &str,
+        directives: &Directives,
+    ) -> Result<Vec<CardInfo>, CoreError> {
+        if directives.ignore || directives.include {
             return Ok(Vec::new());
         }
 
-        use nom::{
-            IResult, Parser, bytes::*, character::*, combinator::*, error::*, multi::*, sequence::*,
+        self.parse_cards(id, path, content, directives)
+            .map_err(|invalid| CoreError::Parsing {
+                span: SourceSpan::new(path, content, invalid.range),
+                reason: invalid.reason,
+            })
+    }
+
+    fn parse_cards(
+        &self,
+        id: u64,
+        path: &str,
+        content: &str,
+        directives: &Directives,
+    ) -> Result<Vec<CardInfo>, Invalid> {
+        let root = typst_syntax::parse(content);
+        let markers = markers(&root)?;
+
+        let Some(first_card) = markers.iter().find_map(|marker| match marker {
+            Marker::Card(card) => Some(card.range.start),
+            _ => None,
+        }) else {
+            // No card in file
+            return Ok(Vec::new());
+        };
+
+        // The header spans from the header directive (if any) to the first card
+        let header = markers
+            .iter()
+            .find_map(|marker| match marker {
+                Marker::Header(start) if *start < first_card => Some(*start),
+                _ => None,
+            })
+            .map(|start| &content[start..first_card])
+            .filter(|header| !header.is_empty())
+            .map(|header| HeaderInfo::new(header, id));
+
+        let file = CardFile {
+            path,
+            content,
+            root: &root,
+            header,
+            directives,
         };
+        let mut cards = Vec::new();
+        // Card currently being read, with its #answer once found
+        let mut current: Option<(CardHeader, Option<Range<usize>>)> = None;
+
+        for marker in markers {
+            match (marker, current.as_mut()) {
+                (Marker::Card(next), _) => {
+                    let end = next.range.start;
+                    if let Some(card) = current.replace((next, None)) {
+                        cards.append(&mut file.finish_card(card, end)?);
+                    }
+                }
+                // First #answer of a card, anything after that is part of the answer
+                (Marker::Answer(range), Some((_, answer @ None))) => *answer = Some(range),
+                _ => {}
+            }
+        }
 
-        // Parsing stuff
+        if let Some(card) = current {
+            cards.append(&mut file.finish_card(card, content.len())?);
+        }
+
+        Ok(cards)
+    }
+}
+
+/// Why and where a card file couldn't be parsed
+struct Invalid {
+    range: Range<usize>,
+    reason: String,
+}
 
-        pub fn ws<'a, O, E: ParseError<&'a str>, F>(
-            inner: F,
-        ) -> impl Parser<&'a str, Output = O, Error = E>
-        where
-            F: Parser<&'a str, Output = O, Error = E>,
+impl Invalid {
+    fn new(range: Range<usize>, reason: impl Into<String>) -> Self {
+        Self {
+            range,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// A `#card(id, name, locations)` (or `#cloze(...)`) call found at the top level of a file
+struct CardHeader {
+    id: String,
+    name: String,
+    locations: Vec<String>,
+    /// Whether the call is a `#cloze`, which stands for a card per deletion group
+    cloze: bool,
+    /// Byte range of the call, including the leading `#`
+    range: Range<usize>,
+}
+
+/// Things of interest at the top level of a card file
+enum Marker {
+    /// Start of a `//![FLASHBANG HEADER]` comment
+    Header(usize),
+    Card(CardHeader),
+    /// Byte range of an `#answer`, including the leading `#`
+    Answer(Range<usize>),
+}
+
+/// Find the markers in a parsed file. Only the top level markup is considered, so anything
+/// inside of strings, comments, raw blocks or content blocks is left alone.
+fn markers(root: &SyntaxNode) -> Result<Vec<Marker>, Invalid> {
+    let mut markers = Vec::new();
+
+    for node in LinkedNode::new(root).children() {
+        // Embedded code expressions start with a hash, which belongs to the range of the marker
+        let start = if node.prev_sibling_kind() == Some(SyntaxKind::Hash) {
+            node.offset() - 1
+        } else {
+            node.offset()
+        };
+        let range = start..node.range().end;
+
+        match node.kind() {
+            SyntaxKind::LineComment if directives::is_header(node.text()) => {
+                markers.push(Marker::Header(start));
+            }
+            SyntaxKind::Ident if node.text() == "answer" => markers.push(Marker::Answer(range)),
+            SyntaxKind::FuncCall => {
+                let Some(call) = node.cast::<ast::FuncCall>() else {
+                    continue;
+                };
+
+                let ast::Expr::Ident(ident) = call.callee() else {
+                    continue;
+                };
+                if matches!(ident.as_str(), "card" | "cloze") {
+                    let cloze = ident.as_str() == "cloze";
+                    markers.push(Marker::Card(card_header(&node, call, range, cloze)?));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(markers)
+}
+
+/// Read the arguments of a `#card` or `#cloze` call
+fn card_header(
+    node: &LinkedNode,
+    call: ast::FuncCall,
+    range: Range<usize>,
+    cloze: bool,
+) -> Result<CardHeader, Invalid> {
+    if call.to_untyped().erroneous() {
+        // An unterminated header swallows the rest of the file, only keep its first line
+        let text = call.to_untyped().clone().into_text();
+        let end = node.offset() + text.find('\n').unwrap_or(text.len());
+
+        return Err(Invalid::new(range.start..end, syntax_error_reason(node)));
+    }
+
+    let error = |reason: &str| Invalid::new(range.clone(), reason);
+
+    let mut args = call.args().items();
+
+    let mut string = |what: &str| match args.next() {
+        Some(ast::Arg::Pos(ast::Expr::Str(str))) => Ok(str.get().to_string()),
+        Some(_) => Err(error(&format!("expected a string for the {what}"))),
+        None => Err(error(&format!("missing {what} argument"))),
+    };
+
+    let id = string("id")?;
+    let name = string("name")?;
+
+    let locations = match args.next() {
+        Some(ast::Arg::Pos(ast::Expr::Array(array))) => array
+            .items()
+            .map(|item| match item {
+                ast::ArrayItem::Pos(ast::Expr::Str(str)) => Ok(str.get().to_string()),
+                _ => Err(error("expected a string for each location")),
+            })
+            .try_collect()?,
+        // A single location without a trailing comma isn't an array
+        Some(ast::Arg::Pos(ast::Expr::Parenthesized(paren))) => match paren.expr() {
+            ast::Expr::Str(str) => vec![str.get().to_string()],
+            _ => return Err(error("expected a string for each location")),
+        },
+        Some(_) => return Err(error("expected a tuple for the locations")),
+        None => return Err(error("missing locations argument")),
+    };
+
+    if args.next().is_some() {
+        return Err(error("too many arguments"));
+    }
+
+    Ok(CardHeader {
+        id,
+        name,
+        locations,
+        cloze,
+        range,
+    })
+}
+
+/// Describe the syntax errors in an erroneous `#card` call
+fn syntax_error_reason(node: &LinkedNode) -> String {
+    fn errors<'a>(node: LinkedNode<'a>, out: &mut Vec<LinkedNode<'a>>) {
+        if node.kind() == SyntaxKind::Error {
+            out.push(node);
+        } else if node.erroneous() {
+            node.children().for_each(|child| errors(child, out));
+        }
+    }
+
+    let mut found = Vec::new();
+    errors(node.clone(), &mut found);
+
+    let unclosed = |parents: &[SyntaxKind]| {
+        found.iter().any(|error| {
+            error.text() == "("
+                && error
+                    .parent_kind()
+                    .is_some_and(|parent| parents.contains(&parent))
+        })
+    };
+
+    if unclosed(&[SyntaxKind::Array, SyntaxKind::Parenthesized]) {
+        "unterminated locations tuple".to_owned()
+    } else if found.iter().any(|error| error.text().starts_with('"')) {
+        "unterminated string".to_owned()
+    } else if unclosed(&[SyntaxKind::Args]) {
+        "unterminated argument list".to_owned()
+    } else {
+        match found.first().map(|error| error.get().errors()) {
+            Some(errors) if !errors.is_empty() => format!("syntax error: {}", errors[0].message),
+            _ => "malformed card header".to_owned(),
+        }
+    }
+}
+
+/// Groups of the deletions (`#c[...]`, `#c(2)[...]`) in a range of a card file, sorted and
+/// without duplicates. Unnumbered deletions are numbered by their position among the
+/// unnumbered ones, the same way the `c` function of cards_internal.typ does. That only holds
+/// if each of them is evaluated once and in order, so they can't be in code blocks, loops,
+/// conditionals, functions or show rules.
+fn deletion_groups(root: &SyntaxNode, range: Range<usize>) -> Result<Vec<u32>, Invalid> {
+    fn walk(
+        node: LinkedNode,
+        range: &Range<usize>,
+        nested: bool,
+        unnumbered: &mut u32,
+        groups: &mut Vec<u32>,
+    ) -> Result<(), Invalid> {
+        if node.range().end <= range.start || node.offset() >= range.end {
+            return Ok(());
+        }
+
+        if let Some(call) = node.cast::<ast::FuncCall>()
+            && matches!(call.callee(), ast::Expr::Ident(ident) if ident.as_str() == "c")
         {
-            delimited(multispace0(), inner, multispace0())
+            let group = match call.args().items().next() {
+                Some(ast::Arg::Pos(ast::Expr::Int(int))) => u32::try_from(int.get())
+                    .ok()
+                    .filter(|&group| group > 0)
+                    .ok_or_else(|| {
+                        Invalid::new(node.range(), "deletion groups are numbered from 1")
+                    })?,
+                _ if nested => {
+                    return Err(Invalid::new(
+                        node.range(),
+                        "unnumbered deletions can't be in code blocks, loops, conditionals, \
+                         functions or show rules, give them a group: #c(1)[...]",
+                    ));
+                }
+                _ => {
+                    *unnumbered += 1;
+                    *unnumbered
+                }
+            };
+            groups.push(group);
         }
 
-        fn string(input: &str) -> IResult<&str, &str> {
-            let (input, (_, content, _)) = (tag("\""), take_until("\""), tag("\"")).parse(input)?;
-            Ok((input, content))
+        let nested = nested
+            || matches!(
+                node.kind(),
+                SyntaxKind::CodeBlock
+                    | SyntaxKind::ForLoop
+                    | SyntaxKind::WhileLoop
+                    | SyntaxKind::Conditional
+                    | SyntaxKind::Closure
+                    | SyntaxKind::ShowRule
+            );
+        for child in node.children() {
+            walk(child, range, nested, unnumbered, groups)?;
         }
+        Ok(())
+    }
 
-        fn card_header(input: &str) -> IResult<&str, (&str, &str, Vec<&str>)> {
-            let (input, _) = (tag("#"), ws(tag("card"))).parse(input)?;
-            let (input, (_, id, _)) = (tag("("), ws(string), tag(",")).parse(input)?;
-            let (input, (name, _)) = (ws(string), tag(",")).parse(input)?;
-            let (input, locations) = delimited(
-                ws(tag("(")),
-                terminated(separated_list0(ws(tag(",")), string), opt(ws(tag(",")))),
-                ws(tag(")")),
-            )
-            .parse(input)?;
-            let (input, _) = ws(tag(")")).parse(input)?;
-            Ok((input, (id, name, locations)))
+    let mut groups = Vec::new();
+    walk(LinkedNode::new(root), &range, false, &mut 0, &mut groups)?;
+
+    Ok(groups.into_iter().sorted().dedup().collect())
+}
+
+/// A card file being parsed
+struct CardFile<'a> {
+    path: &'a str,
+    content: &'a str,
+    root: &'a SyntaxNode,
+    header: Option<HeaderInfo>,
+    directives: &'a Directives,
+}
+
+impl CardFile<'_> {
+    /// Build the info of a card given its #answer and where it ends, or of the cards of a
+    /// cloze: one per deletion group, hiding that group.
+    fn finish_card(
+        &self,
+        (card, answer): (CardHeader, Option<Range<usize>>),
+        end: usize,
+    ) -> Result<Vec<CardInfo>, Invalid> {
+        if card.cloze {
+            if let Some(answer) = answer {
+                return Err(Invalid::new(
+                    answer,
+                    format!("cloze \"{}\" can't have an #answer", card.id),
+                ));
+            }
+
+            // The same text is shown on both sides, only the deletions change
+            let body = card.range.end..end;
+            let groups = deletion_groups(self.root, body.clone())?;
+            if groups.is_empty() {
+                return Err(Invalid::new(
+                    card.range,
+                    format!("cloze \"{}\" has no deletion (#c[...])", card.id),
+                ));
+            }
+
+            return Ok(groups
+                .into_iter()
+                .map(|group| self.card_info(&card, body.clone(), body.clone(), Some(group)))
+                .collect());
         }
 
-        fn card(input: &str) -> IResult<&str, (&str, &str, Vec<&str>, &str, &str)> {
-            let (input, (id, name, locations)) = card_header(input)?;
-            let (input, (question, _)) = (take_until("#answer"), tag("#answer")).parse(input)?;
-            let (input, answer) = take_until::<&str, &str, Error<&str>>("#card")
-                .parse(input)
-                .unwrap_or(("", input));
-            Ok((input, (id, name, locations, question, answer)))
+        let Some(answer) = answer else {
+            return Err(Invalid::new(
+                card.range,
+                format!("card \"{}\" has no #answer", card.id),
+            ));
+        };
+
+        let question = card.range.end..answer.start;
+        let answer = answer.end..end;
+
+        Ok(vec![self.card_info(&card, question, answer, None)])
+    }
+
+    fn card_info(
+        &self,
+        card: &CardHeader,
+        question: Range<usize>,
+        answer: Range<usize>,
+        cloze: Option<u32>,
+    ) -> CardInfo {
+        let (path, content) = (self.path, self.content);
+        // Cards of a cloze are told apart by their group, which keeps their id stable as long
+        // as the deletions are numbered the same
+        let (id, name) = match cloze {
+            Some(group) => (
+                format!("{}.c{group}", card.id),
+                format!("{} ({group})", card.name),
+            ),
+            None => (card.id.clone(), card.name.clone()),
+        };
+
+        CardInfo {
+            id,
+            deck: String::new(),
+            name,
+            locations: self.directives.locations(card.locations.clone()),
+            header: self.header.clone(),
+            question: content[question.clone()].to_owned(),
+            answer: content[answer.clone()].to_owned(),
+            origin: CardOrigin {
+                header: SourceSpan::new(path, content, card.range.clone()),
+                question: SourceSpan::new(path, content, question),
+                answer: SourceSpan::new(path, content, answer),
+            },
+            suspended: self.directives.suspend,
+            authors: self.directives.authors.clone(),
+            cloze,
         }
+    }
+}
 
-        // Skip before header (if any)
-        let (content, has_header) =
-            match take_until::<&str, &str, Error<&str>>("//![FLASHBANG HEADER]").parse(content) {
-                // Found header section
-                Ok((content, _)) => (content, true),
-                // No header section, continue as usual
-                _ => (content, false),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cards() {
+        let content = "//![FLASHBANG HEADER]\n#import \"lib.typ\": *\n\
+            #card(\"a\", \"First\", (\"x.y\", \"z\"))\nQ1\n#answer\nA1\n\
+            #card(\"b\", \"Second\", (\"w\"))\nQ2\n#answer\nA2\n";
+        let cards = CardState::new().parse(0, "test.typ", content).unwrap();
+
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].id, "a");
+        assert_eq!(cards[0].name, "First");
+        assert_eq!(cards[0].locations, vec!["x.y", "z"]);
+        assert_eq!(cards[0].question, "\nQ1\n");
+        assert_eq!(cards[0].answer, "\nA1\n");
+        assert_eq!(cards[1].locations, vec!["w"]);
+        assert_eq!(cards[1].answer, "\nA2\n");
+        assert_eq!(
+            cards[0].header.as_ref().unwrap().inner,
+            "//![FLASHBANG HEADER]\n#import \"lib.typ\": *\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_directives() {
+        let state = CardState::new();
+        let content = "//![FLASHBANG LOCATIONS default=\"x, y\" prefix=algebra]\n\
+            //![FLASHBANG SUSPEND]\n//![FLASHBANG AUTHOR name=\"Jane Doe\"]\n\
+            //![FLASHBANG HEADER]\n#import \"lib.typ\": *\n\
+            #card(\"a\", \"A\", ())\nQ\n#answer\nA\n\
+            #card(\"b\", \"B\", (\"z\",))\nQ\n#answer\nA\n";
+        let cards = state.parse(0, "test.typ", content).unwrap();
+
+        assert_eq!(cards[0].locations, ["algebra.x", "algebra.y"]);
+        assert_eq!(cards[1].locations, ["algebra.z"]);
+        assert!(cards.iter().all(|card| card.suspended));
+        assert_eq!(cards[0].authors, ["Jane Doe"]);
+        assert!(
+            cards[0]
+                .header
+                .as_ref()
+                .unwrap()
+                .inner
+                .starts_with("//![FLASHBANG HEADER]")
+        );
+
+        // Directives can be anywhere in the preamble
+        let ignored = "// Drafts\n\n//![FLASHBANG IGNORE]\n#card(\"a\", \"A\", ())\nQ\n";
+        assert!(state.parse(0, "test.typ", ignored).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_cloze() {
+        let state = CardState::new();
+        let content = "#cloze(\"f\", \"Formula\", (\"x\",))\n\
+            #c[a] + #c(3)[b] = *#c[c]* #c(3)[d]\n\
+            #card(\"g\", \"G\", ())\nQ\n#answer\n#c[A]\n";
+        let cards = state.parse(0, "test.typ", content).unwrap();
+
+        let groups = cards
+            .iter()
+            .map(|card| (card.id.as_str(), card.cloze))
+            .collect_vec();
+        assert_eq!(
+            groups,
+            [
+                ("f.c1", Some(1)),
+                ("f.c2", Some(2)),
+                ("f.c3", Some(3)),
+                ("g", None)
+            ]
+        );
+        assert_eq!(cards[0].name, "Formula (1)");
+        assert_eq!(cards[0].locations, ["x"]);
+        assert_eq!(cards[0].question, "\n#c[a] + #c(3)[b] = *#c[c]* #c(3)[d]\n");
+        assert_eq!(cards[0].question, cards[0].answer);
+        assert_eq!(cards[0].origin.question, cards[2].origin.answer);
+
+        let header = codegen::card_header(&cards[2]).unwrap();
+        assert_eq!(
+            header,
+            "#card(\"f.c3\", \"Formula (3)\", (\"x\",), cloze: 3)"
+        );
+
+        assert!(
+            state
+                .parse(0, "test.typ", "#cloze(\"f\", \"F\", ())\nNothing\n")
+                .is_err()
+        );
+        assert!(
+            state
+                .parse(0, "test.typ", "#cloze(\"f\", \"F\", ())\n#c(0)[a]\n")
+                .is_err()
+        );
+        assert!(
+            state
+                .parse(0, "test.typ", "#cloze(\"f\", \"F\", ())\n#c[a]\n#answer\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_cloze_nested() {
+        let state = CardState::new();
+        let cloze = |body: &str| {
+            let content = format!("#cloze(\"f\", \"F\", ())\n{body}\n");
+            state.parse(0, "test.typ", &content)
+        };
+
+        // Evaluated once each, in order
+        let cards = cloze("#[#c[a]] #box(c[b]) #c[#c[c]]").unwrap();
+        assert_eq!(cards.len(), 4);
+        let cards = cloze("#for x in (1, 2) [#c(1)[#x]] #if true { c(2)[b] }").unwrap();
+        assert_eq!(cards.len(), 2);
+
+        // The runtime counter could disagree with their position
+        for body in [
+            "#for x in (1, 2) [#c[#x]]",
+            "#while false { c[a] }",
+            "#if false [#c[a]] #c[b]",
+            "#{ c[a] }",
+            "#let f(x) = c[x]\n#f[a]",
+            "#show \"x\": it => c(it)\nx",
+        ] {
+            let Err(CoreError::Parsing { reason, .. }) = cloze(body) else {
+                panic!("expected {body:?} to be refused");
             };
+            assert!(reason.contains("give them a group"), "{reason}");
+        }
+    }
 
-        // Parse header
-        let Ok((content, header)) = take_until::<&str, &str, Error<&str>>("#card").parse(content)
-        else {
-            // No card in file
-            return Ok(Vec::new());
+    #[test]
+    fn test_parse_ignores_nested_markers() {
+        let content = "#card(\"a\", \"A \\\"quoted\\\" name\", ())\n\
+            `#answer` // #answer\n#\"#card\"\n#answer\n\
+            #[ #card(\"b\", \"B\", ()) ] /* #card */ ```typ #card```\n";
+        let cards = CardState::new().parse(0, "test.typ", content).unwrap();
+
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].name, "A \"quoted\" name");
+        assert!(cards[0].question.contains("`#answer`"));
+        assert!(cards[0].answer.contains("#card(\"b\""));
+        assert!(cards[0].header.is_none());
+    }
+
+    #[test]
+    fn test_source_map() {
+        let content = "#card(\"a\", \"A\", (\"x\",))\nFirst question\n#answer\nFirst answer\n\n\
+            #card(\"b\", \"B\", (\"x\",)) Second question #answer\n\nSecond answer";
+        let state = CardState::new();
+        let cards = state.parse(0, "deck/file.typ", content).unwrap();
+        let config = SourceConfig {
+            page_width: 300,
+            text_size: 12,
+            text_color: 0,
+            sans_math: false,
         };
+        let (source, map) = state.build_source(&cards, config).unwrap();
 
-        // Save header
-        let header = if !header.is_empty() && has_header {
-            Some(HeaderInfo::new(header, id))
-        } else {
-            None
+        let resolve = |needle: &str| {
+            let start = source.find(needle).unwrap();
+            let (id, span) = map.resolve(&source, start..start + needle.len()).unwrap();
+            assert_eq!(&content[span.start as usize..span.end as usize], needle);
+            (id.to_owned(), span.file, span.line, span.column)
         };
 
-        // Parse cards
-        let (_, (cards, _)) = many_till(card, eof).parse(content)?;
-
-        // Resolve tags and names
-        Ok(cards
-            .into_iter()
-            .map(|(id, name, locations, question, answer)| CardInfo {
-                id: id.to_owned(),
-                name: name.to_owned(),
-                locations: locations.into_iter().map(ToOwned::to_owned).collect_vec(),
-                header: header.clone(),
-                question: question.to_owned(),
-                answer: answer.to_owned(),
-            })
-            .collect_vec())
+        assert_eq!(
+            resolve("First answer"),
+            ("a".into(), "deck/file.typ".into(), 4, 1)
+        );
+        assert_eq!(
+            resolve("Second question"),
+            ("b".into(), "deck/file.typ".into(), 6, 25)
+        );
+        assert_eq!(
+            resolve("Second answer"),
+            ("b".into(), "deck/file.typ".into(), 8, 1)
+        );
+
+        let header = source.find("#card(\"b\"").unwrap();
+        let (_, span) = map.resolve(&source, header..header + 1).unwrap();
+        assert_eq!((span.line, span.column), (6, 1));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let state = CardState::new();
+
+        assert!(
+            state
+                .parse(0, "test.typ", "#card(\"a\", \"A\", ())\nno answer")
+                .is_err()
+        );
+        assert!(
+            state
+                .parse(0, "test.typ", "#card(\"a\", ())\n#answer")
+                .is_err()
+        );
+        assert!(
+            state
+                .parse(0, "test.typ", "#card(\"a\", \"A\", (1,))\n#answer")
+                .is_err()
+        );
+        let reason = |content: &str| match state.parse(0, "test.typ", content) {
+            Err(CoreError::Parsing { span, reason }) => (span.line, span.column, reason),
+            _ => panic!("expected a parsing error"),
+        };
+
+        assert_eq!(
+            reason("Intro\n  #card(\"a\")\n#answer"),
+            (2, 3, "missing name argument".to_owned())
+        );
+        assert_eq!(
+            reason("#card(\"a\", \"A\", (\"x\",\nQ\n#answer"),
+            (1, 1, "unterminated locations tuple".to_owned())
+        );
     }
 }
@@ -1,10 +1,55 @@
 #set page(height: auto, margin: 1em, fill: none)
 #set text(font: "Lexend")
 
-#let card(id, name, tags) = {
+// Deletion group hidden by the current card (none unless it comes from a #cloze), and whether
+// the answer is being shown
+#let _cloze = state("_flashbang_cloze", (group: none, reveal: false))
+// Unnumbered deletions seen so far on the current side of the card
+#let _deletions = counter("_flashbang_deletions")
+
+// Every part of a card starts on a new page, marked with metadata that is used to find which
+// pages belong to which card (see WorldState::compile).
+#let card(id, name, tags, cloze: none) = {
   pagebreak()
+  _cloze.update((group: cloze, reveal: false))
+  _deletions.update(0)
+  [#metadata((kind: "question", id: id)) <_flashbang_part>]
 }
 
 #let answer = {
   pagebreak()
+  _cloze.update(cloze => (..cloze, reveal: true))
+  _deletions.update(0)
+  [#metadata((kind: "answer")) <_flashbang_part>]
+}
+
+// A deletion of a cloze card: `#c[...]`, or `#c(2)[...]` to hide it along with the other
+// deletions numbered 2. Unnumbered deletions are numbered by their position among the
+// unnumbered ones (see cards.rs). The deletions of the card's group are hidden on the question
+// side and highlighted on the answer side, the others are shown as is.
+#let c(..args) = {
+  let (group, body) = if args.pos().len() == 2 { args.pos() } else { (none, args.pos().at(0)) }
+  if group == none {
+    _deletions.step()
+  }
+
+  context {
+    let cloze = _cloze.get()
+    let group = if group == none { _deletions.get().at(0) } else { group }
+
+    if cloze.group != group {
+      body
+    } else if cloze.reveal {
+      underline(strong(body))
+    } else {
+      strong[\[…\]]
+    }
+  }
+}
+
+// Shown in place of a card that failed to compile
+#let _card_error(message) = {
+  text(fill: red, weight: "bold")[This card couldn't be compiled]
+  parbreak()
+  text(size: 0.8em, message)
 }
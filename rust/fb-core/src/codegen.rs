@@ -0,0 +1,193 @@
+//! Generation of typst code from card data, every value coming from a card goes through here
+//! so that it can't break out of the code it is placed in.
+
+use std::fmt::Write;
+
+use crate::{
+    cards::CardSource,
+    error::{CoreError, SourceSpan},
+    source_map::{SourceMap, SourceMapEntry},
+};
+
+/// Generated source, keeping track of lines to build its source map
+pub struct SourceBuilder {
+    buffer: Vec<u8>,
+    /// Current line, starting at 1
+    line: u32,
+    map: SourceMap,
+}
+
+impl SourceBuilder {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            line: 1,
+            map: SourceMap::new(),
+        }
+    }
+
+    /// Start a new line unless already at the start of one
+    pub fn ensure_newline(&mut self) {
+        if self.buffer.last().is_some_and(|&c| c != b'\n') {
+            self.buffer.push(b'\n');
+            self.line += 1;
+        }
+    }
+
+    /// Write text coming from a card, recording where it comes from in the source map
+    pub fn push_mapped(
+        &mut self,
+        text: &str,
+        card_id: &str,
+        origin: Option<&SourceSpan>,
+        verbatim: bool,
+    ) {
+        let start = self.buffer.len();
+        let first_line = self.line;
+
+        self.buffer.extend_from_slice(text.as_bytes());
+        self.line += text.matches('\n').count() as u32;
+
+        if let Some(origin) = origin
+            && !text.is_empty()
+        {
+            self.map.push(SourceMapEntry {
+                card_id: card_id.to_owned(),
+                start: start as u64,
+                end: self.buffer.len() as u64,
+                first_line,
+                last_line: self.line - text.ends_with('\n') as u32,
+                origin: origin.clone(),
+                verbatim,
+            });
+        }
+    }
+
+    pub fn finish(self) -> Result<(String, SourceMap), CoreError> {
+        let text = String::from_utf8(self.buffer).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::Other, "Couldn't parse bytes to string")
+        })?;
+
+        Ok((text, self.map))
+    }
+}
+
+impl std::io::Write for SourceBuilder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.line += buf.iter().filter(|&&c| c == b'\n').count() as u32;
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Quote a string as a typst string literal
+pub fn string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{{{:x}}}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Check that a card id is usable
+fn validate_id(id: &str) -> Result<(), String> {
+    if id.trim().is_empty() {
+        Err("id is empty".to_owned())
+    } else if id.chars().any(char::is_control) {
+        Err("id contains control characters".to_owned())
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that a location (dot separated path of tags) is usable
+fn validate_location(location: &str) -> Result<(), String> {
+    if location.chars().any(char::is_control) {
+        Err(format!(
+            "location {} contains control characters",
+            string(location)
+        ))
+    } else if location.split('.').any(|tag| tag.trim().is_empty()) {
+        Err(format!("location {} has an empty tag", string(location)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Generate the `#card(id, name, locations)` call of a card, along with the deletion group of
+/// cloze cards
+pub fn card_header<C: CardSource>(card: &C) -> Result<String, CoreError> {
+    let id = card.id();
+    let invalid = |reason| CoreError::InvalidCard {
+        id: id.clone(),
+        reason,
+    };
+
+    validate_id(&id).map_err(invalid)?;
+
+    let mut locations = String::new();
+    for location in card.locations() {
+        validate_location(&location).map_err(invalid)?;
+        locations.push_str(&string(&location));
+        locations.push(',');
+    }
+
+    let cloze = match card.cloze() {
+        Some(group) => format!(", cloze: {group}"),
+        None => String::new(),
+    };
+
+    Ok(format!(
+        "#card({}, {}, ({locations}){cloze})",
+        string(&id),
+        string(&card.name())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_escaping() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(string("line\nbreak\u{7}"), "\"line\\nbreak\\u{7}\"");
+
+        // The escaped literal must read back as the original value
+        let value = "\")] #panic(\"injected\") \\";
+        let root = typst_syntax::parse_code(&string(value));
+        let literal = root
+            .children()
+            .find_map(|node| node.cast::<typst_syntax::ast::Str>())
+            .expect("a string literal");
+        assert_eq!(literal.get().as_str(), value);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_id("card-1").is_ok());
+        assert!(validate_id(" ").is_err());
+        assert!(validate_location("math.algebra").is_ok());
+        assert!(validate_location("math..algebra").is_err());
+        assert!(validate_location("").is_err());
+    }
+}
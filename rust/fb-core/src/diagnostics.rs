@@ -0,0 +1,69 @@
+//! Typst diagnostics in a form that can cross the ffi boundaries
+
+use std::fmt::Display;
+
+use crate::error::SourceSpan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A step of the trace leading to a diagnostic (function call, show rule, import)
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(
+    feature = "wasm",
+    wasm_bindgen::prelude::wasm_bindgen(getter_with_clone)
+)]
+pub struct TracePoint {
+    pub message: String,
+    pub location: Option<SourceSpan>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(
+    feature = "wasm",
+    wasm_bindgen::prelude::wasm_bindgen(getter_with_clone)
+)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub hints: Vec<String>,
+    pub trace: Vec<TracePoint>,
+    /// File the diagnostic is in as typst sees it (`/_main.typ`, `@preview/pkg:0.1.0/lib.typ`),
+    /// none if the diagnostic isn't attached to any file.
+    pub file_id: Option<String>,
+    /// Byte offset of the start of the span in that file
+    pub start: u64,
+    /// Byte offset of the end of the span in that file
+    pub end: u64,
+    /// Location of the diagnostic, mapped back to the deck file when it comes from a card
+    pub location: Option<SourceSpan>,
+    /// Id of the card the diagnostic comes from, if any
+    pub card_id: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        match &self.location {
+            Some(location) => write!(f, "{location}: {severity}: {}", self.message)?,
+            None => write!(f, "{severity}: {}", self.message)?,
+        }
+
+        for hint in &self.hints {
+            write!(f, " (hint: {hint})")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Card level differences between two revisions of a deck
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use typst::utils::hash128;
+
+use crate::cards::{CardInfo, CardSource};
+
+/// What changed in a deck between two revisions. A card can be both moved and modified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct DeckDiff {
+    /// Sha of the previous revision, empty if unknown
+    pub old_sha: String,
+    /// Sha of the new revision, empty if unknown
+    pub new_sha: String,
+    /// Cards only in the new revision
+    pub added: Vec<CardChange>,
+    /// Cards only in the previous revision
+    pub removed: Vec<CardChange>,
+    /// Cards that were renamed, moved to other locations or to another file
+    pub moved: Vec<CardChange>,
+    /// Cards whose content (header, question or answer) changed
+    pub modified: Vec<CardChange>,
+}
+
+/// A card that changed between two revisions
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CardChange {
+    pub id: String,
+    /// The card in the previous revision, None if it was added
+    pub old: Option<CardRevision>,
+    /// The card in the new revision, None if it was removed
+    pub new: Option<CardRevision>,
+}
+
+/// A card as it is in one revision of a deck
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CardRevision {
+    /// Hash of the content of the card, see [`content_hash`]
+    pub hash: String,
+    pub name: String,
+    pub locations: Vec<String>,
+    /// File the card is in
+    pub file: String,
+}
+
+impl CardRevision {
+    fn new(card: &CardInfo) -> Self {
+        Self {
+            hash: content_hash(card),
+            name: card.name.clone(),
+            locations: card.locations.clone(),
+            file: card.origin.header.file.clone(),
+        }
+    }
+}
+
+/// Hash of what is rendered of a card: its header, question, answer and deletion group, as hex
+pub fn content_hash(card: &impl CardSource) -> String {
+    format!(
+        "{:032x}",
+        hash128(&(
+            card.header_content(),
+            card.question(),
+            card.answer(),
+            card.cloze()
+        ))
+    )
+}
+
+impl DeckDiff {
+    /// Compare the cards of two revisions of a deck, changes are sorted by id
+    pub fn new(old: &[CardInfo], new: &[CardInfo]) -> Self {
+        let old = old
+            .iter()
+            .map(|card| (card.id.as_str(), card))
+            .collect::<HashMap<_, _>>();
+        let new = new
+            .iter()
+            .map(|card| (card.id.as_str(), card))
+            .collect::<HashMap<_, _>>();
+        let mut diff = Self::default();
+
+        for id in old.keys().chain(new.keys()).unique().sorted() {
+            let change = CardChange {
+                id: id.to_string(),
+                old: old.get(id).map(|card| CardRevision::new(card)),
+                new: new.get(id).map(|card| CardRevision::new(card)),
+            };
+
+            match (&change.old, &change.new) {
+                (None, Some(_)) => diff.added.push(change),
+                (Some(_), None) => diff.removed.push(change),
+                (Some(old), Some(new)) => {
+                    let moved = old.name != new.name
+                        || old.locations != new.locations
+                        || old.file != new.file;
+                    let modified = old.hash != new.hash;
+
+                    if moved {
+                        diff.moved.push(change.clone());
+                    }
+                    if modified {
+                        diff.modified.push(change);
+                    }
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::CardState;
+
+    fn parse(path: &str, content: &str) -> Vec<CardInfo> {
+        CardState::new().parse(0, path, content).unwrap()
+    }
+
+    #[test]
+    fn test_deck_diff() {
+        let old = parse(
+            "deck.typ",
+            r#"
+#card("a", "A", ("x",))
+Question A
+#answer
+Answer A
+#card("b", "B", ("x",))
+Question B
+#answer
+Answer B
+#card("c", "C", ("x",))
+Question C
+#answer
+Answer C
+"#,
+        );
+        let new = parse(
+            "deck.typ",
+            r#"
+#card("a", "A", ("x",))
+Question A
+#answer
+Answer A
+#card("b", "B", ("y",))
+Question B, edited
+#answer
+Answer B
+#card("d", "D", ("x",))
+Question D
+#answer
+Answer D
+"#,
+        );
+
+        let diff = DeckDiff::new(&old, &new);
+        let ids = |changes: &[CardChange]| changes.iter().map(|c| c.id.clone()).collect_vec();
+
+        assert_eq!(ids(&diff.added), ["d"]);
+        assert_eq!(ids(&diff.removed), ["c"]);
+        assert_eq!(ids(&diff.moved), ["b"]);
+        assert_eq!(ids(&diff.modified), ["b"]);
+
+        let modified = &diff.modified[0];
+        let (old_b, new_b) = (
+            modified.old.as_ref().unwrap(),
+            modified.new.as_ref().unwrap(),
+        );
+        assert_ne!(old_b.hash, new_b.hash);
+        assert_eq!(old_b.locations, ["x"]);
+        assert_eq!(new_b.locations, ["y"]);
+        assert!(diff.added[0].old.is_none());
+        assert!(diff.removed[0].new.is_none());
+
+        // Moving a card to another file doesn't change its content
+        let moved = parse(
+            "other.typ",
+            "#card(\"a\", \"A\", (\"x\",))\nQuestion A\n#answer\nAnswer A\n",
+        );
+        let diff = DeckDiff::new(&old[..1], &moved);
+        assert_eq!(ids(&diff.moved), ["a"]);
+        assert!(diff.modified.is_empty());
+
+        assert!(DeckDiff::new(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+//! `//![FLASHBANG NAME key=value ...]` directives, telling how a card file is loaded.
+//!
+//! Directives are line comments in the preamble of a file (the lines before anything but blank
+//! lines and line comments). Values can be quoted to hold spaces: `name="Jane Doe"`.
+//!
+//!  - `IGNORE`: the file is skipped.
+//!  - `INCLUDE`: the file is loaded as is for cards to import, no card is looked for in it.
+//!  - `HEADER`: everything from it to the first card is put before each card of the file.
+//!    Unlike the others, it can be anywhere before the first card.
+//!  - `LOCATIONS default="a.b, c" prefix="x"`: locations of the cards without any, and a
+//!    location prepended to the locations of every card of the file.
+//!  - `SUSPEND`: the cards of the file are suspended.
+//!  - `AUTHOR name="Jane Doe"`: an author of the cards of the file, once per author.
+
+use std::fmt::Display;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::error::SourceSpan;
+
+/// A directive as written, see [`parse_directive`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive {
+    pub name: String,
+    /// Arguments, in order. A key can be given several times.
+    pub args: Vec<(String, String)>,
+}
+
+/// A directive that was skipped or partly understood
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct DirectiveWarning {
+    pub message: String,
+    /// The comment holding the directive
+    pub span: SourceSpan,
+}
+
+impl Display for DirectiveWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: warning: {}", self.span, self.message)
+    }
+}
+
+/// What the directives of a file ask for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Directives {
+    pub ignore: bool,
+    pub include: bool,
+    /// Locations of the cards that don't have any
+    pub default_locations: Vec<String>,
+    /// Location the locations of every card are put under
+    pub location_prefix: Option<String>,
+    pub suspend: bool,
+    pub authors: Vec<String>,
+}
+
+/// Parse a line comment as a directive. None if it isn't one, the reason if it is malformed.
+pub fn parse_directive(comment: &str) -> Option<Result<Directive, String>> {
+    let rest = comment.trim().strip_prefix("//![FLASHBANG")?;
+    if !rest.is_empty() && !rest.starts_with([' ', ']']) {
+        return None;
+    }
+
+    let Some(body) = rest.trim_end().strip_suffix(']') else {
+        return Some(Err("missing closing ]".to_owned()));
+    };
+
+    Some(parse_body(body))
+}
+
+fn parse_body(body: &str) -> Result<Directive, String> {
+    /// Characters up to a whitespace or `stop`
+    fn word(chars: &mut Peekable<Chars>, stop: char) -> String {
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != stop) {
+            word.push(c);
+        }
+        word
+    }
+
+    let mut chars = body.trim().chars().peekable();
+    let name = word(&mut chars, ' ');
+    if name.is_empty() {
+        return Err("missing directive name".to_owned());
+    }
+
+    let mut args = Vec::new();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let key = word(&mut chars, '=');
+        if chars.next() != Some('=') || key.is_empty() {
+            return Err(format!("expected key=value, found \"{key}\""));
+        }
+
+        let value = if chars.next_if_eq(&'"').is_some() {
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\')) => value.push(c),
+                        Some(c) => return Err(format!("unknown escape \\{c}")),
+                        None => return Err("unterminated string".to_owned()),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err(format!("expected a space after the value of {key}"));
+            }
+            value
+        } else {
+            word(&mut chars, ' ')
+        };
+
+        args.push((key, value));
+    }
+
+    Ok(Directive { name, args })
+}
+
+impl Directives {
+    /// Read the directives in the preamble of a file, path is only used for the warnings
+    pub fn parse(path: &str, content: &str) -> (Self, Vec<DirectiveWarning>) {
+        let mut directives = Self::default();
+        let mut warnings = Vec::new();
+        let mut offset = 0;
+
+        for line in content.split_inclusive('\n') {
+            let range = offset..offset + line.trim_end().len();
+            offset += line.len();
+
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if !trimmed.starts_with("//") {
+                break;
+            }
+
+            let mut warn = |message: String| {
+                let span = SourceSpan::new(path, content, range.clone());
+                warnings.push(DirectiveWarning { message, span });
+            };
+
+            match parse_directive(trimmed) {
+                Some(Ok(directive)) => directives.apply(directive, &mut warn),
+                Some(Err(reason)) => warn(format!("malformed directive: {reason}")),
+                None => {}
+            }
+        }
+
+        (directives, warnings)
+    }
+
+    fn apply(&mut self, directive: Directive, warn: &mut impl FnMut(String)) {
+        let Directive { name, args } = directive;
+        let keys: &[&str] = match name.as_str() {
+            "IGNORE" | "INCLUDE" | "HEADER" | "SUSPEND" => &[],
+            "LOCATIONS" => &["default", "prefix"],
+            "AUTHOR" => &["name"],
+            _ => return warn(format!("unknown directive {name}, it is skipped")),
+        };
+
+        for (key, _) in &args {
+            if !keys.contains(&key.as_str()) {
+                warn(format!("unknown argument {key} of {name}, it is skipped"));
+            }
+        }
+        let values = |key: &'static str| {
+            args.iter()
+                .filter(move |(arg, _)| arg == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        match name.as_str() {
+            "IGNORE" => self.ignore = true,
+            "INCLUDE" => self.include = true,
+            "SUSPEND" => self.suspend = true,
+            "LOCATIONS" => {
+                let defaults = values("default").flat_map(|value| value.split(','));
+                self.default_locations.extend(
+                    defaults
+                        .map(str::trim)
+                        .filter(|location| !location.is_empty())
+                        .map(str::to_owned),
+                );
+                if let Some(prefix) = values("prefix").next_back() {
+                    self.location_prefix = Some(prefix.trim().trim_matches('.').to_owned())
+                        .filter(|prefix| !prefix.is_empty());
+                }
+            }
+            "AUTHOR" => match values("name").next_back() {
+                Some(author) if !author.trim().is_empty() => {
+                    self.authors.push(author.trim().to_owned());
+                }
+                _ => warn("AUTHOR needs a name, it is skipped".to_owned()),
+            },
+            _ => {}
+        }
+    }
+
+    /// Locations of a card of the file, given the ones it was declared with
+    pub fn locations(&self, locations: Vec<String>) -> Vec<String> {
+        let locations = if locations.is_empty() {
+            self.default_locations.clone()
+        } else {
+            locations
+        };
+
+        match &self.location_prefix {
+            Some(prefix) => locations
+                .into_iter()
+                .map(|location| format!("{prefix}.{location}"))
+                .collect(),
+            None => locations,
+        }
+    }
+}
+
+/// Whether a line comment is a `HEADER` directive
+pub(crate) fn is_header(comment: &str) -> bool {
+    matches!(parse_directive(comment), Some(Ok(directive)) if directive.name == "HEADER")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directive(name: &str, args: &[(&str, &str)]) -> Option<Result<Directive, String>> {
+        let args = args
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Some(Ok(Directive {
+            name: name.to_owned(),
+            args,
+        }))
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        assert_eq!(
+            parse_directive("//![FLASHBANG IGNORE]"),
+            directive("IGNORE", &[])
+        );
+        assert_eq!(
+            parse_directive("  //![FLASHBANG AUTHOR name=\"Jane \\\"J\\\" Doe ]\" x=y]  "),
+            directive("AUTHOR", &[("name", "Jane \"J\" Doe ]"), ("x", "y")])
+        );
+        assert_eq!(parse_directive("// just a comment"), None);
+        assert_eq!(parse_directive("//![FLASHBANGING]"), None);
+
+        let malformed = |comment: &str| matches!(parse_directive(comment), Some(Err(_)));
+        assert!(malformed("//![FLASHBANG IGNORE"));
+        assert!(malformed("//![FLASHBANG ]"));
+        assert!(malformed("//![FLASHBANG AUTHOR name]"));
+        assert!(malformed("//![FLASHBANG AUTHOR name=\"Jane]"));
+    }
+
+    #[test]
+    fn test_parse_directives() {
+        let content = "// Linear algebra\n\n\
+            //![FLASHBANG LOCATIONS default=\"groups, rings\" prefix=algebra.]\n\
+            //![FLASHBANG SUSPEND]\n\
+            //![FLASHBANG AUTHOR name=\"Jane Doe\"]\n\
+            //![FLASHBANG AUTHOR name=John]\n\
+            //![FLASHBANG TYPO]\n\
+            //![FLASHBANG SUSPEND until=tomorrow]\n\
+            #import \"lib.typ\": *\n\
+            //![FLASHBANG IGNORE]\n";
+        let (directives, warnings) = Directives::parse("file.typ", content);
+
+        assert!(directives.suspend);
+        // Past the preamble
+        assert!(!directives.ignore);
+        assert_eq!(directives.authors, ["Jane Doe", "John"]);
+        assert_eq!(
+            directives.locations(Vec::new()),
+            ["algebra.groups", "algebra.rings"]
+        );
+        assert_eq!(
+            directives.locations(vec!["fields".into()]),
+            ["algebra.fields"]
+        );
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].message, "unknown directive TYPO, it is skipped");
+        assert_eq!((warnings[0].span.line, warnings[0].span.column), (7, 1));
+        assert_eq!(&content[warnings[0].span.start as usize..][..2], "//");
+        assert!(warnings[1].message.contains("until"));
+    }
+}
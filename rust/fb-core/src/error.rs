@@ -1,18 +1,75 @@
-use std::error::Error;
+use std::{error::Error, fmt::Display, ops::Range};
+
+#[cfg(feature = "compile")]
+use itertools::Itertools;
+
+#[cfg(feature = "compile")]
+use crate::diagnostics::Diagnostic;
+
+/// Location of something in a typst file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(
+    feature = "wasm",
+    wasm_bindgen::prelude::wasm_bindgen(getter_with_clone)
+)]
+pub struct SourceSpan {
+    /// Path of the file
+    pub file: String,
+    /// Line of the start of the span, starting at 1
+    pub line: u32,
+    /// Column of the start of the span in characters, starting at 1
+    pub column: u32,
+    /// Byte offset of the start of the span
+    pub start: u64,
+    /// Byte offset of the end of the span
+    pub end: u64,
+}
+
+impl SourceSpan {
+    /// Build a span from a byte range in the content of a file
+    pub fn new(file: &str, content: &str, range: Range<usize>) -> Self {
+        let before = &content[..range.start];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        Self {
+            file: file.to_owned(),
+            line: before.matches('\n').count() as u32 + 1,
+            column: before[line_start..].chars().count() as u32 + 1,
+            start: range.start as u64,
+            end: range.end as u64,
+        }
+    }
+}
+
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 #[cfg_attr(feature = "uniffi", derive(uniffi::Error))]
 pub enum CoreError {
-    #[error("couldn't parse typst file for cards: {details}")]
-    Parsing { details: String },
+    #[error("couldn't parse cards at {span}: {reason}")]
+    Parsing { span: SourceSpan, reason: String },
+    #[error("invalid card \"{id}\": {reason}")]
+    InvalidCard { id: String, reason: String },
     #[error("IO error: {details}")]
     IO { details: String },
     #[cfg(feature = "github")]
     #[error("Http (Reqwest) error: {details}")]
     HTTP { details: String },
+    /// The server won't answer more requests for now
+    #[cfg(feature = "github")]
+    #[error("Rate limit exceeded, retry after {}", retry_time(*.retry_at))]
+    RateLimited {
+        /// Time at which requests can be made again, in seconds since the unix epoch
+        retry_at: u64,
+    },
     #[cfg(feature = "compile")]
-    #[error("Typst error: {details}")]
-    Typst { details: String },
+    #[error("Typst error: {}", .diagnostics.iter().join("\n"))]
+    Typst { diagnostics: Vec<Diagnostic> },
     #[cfg(feature = "scheduler")]
     #[error("FSRS error: {details}")]
     FSRS { details: String },
@@ -20,21 +77,6 @@ pub enum CoreError {
     Other { details: String },
 }
 
-impl<'a> From<nom::Err<nom::error::Error<&'a str>>> for CoreError {
-    fn from(value: nom::Err<nom::error::Error<&'a str>>) -> Self {
-        Self::Parsing {
-            details: match value {
-                nom::Err::Incomplete(needed) => match needed {
-                    nom::Needed::Unknown => format!("Incomplete input"),
-                    nom::Needed::Size(size) => format!("Incomplete input, missing ({size}) bytes"),
-                },
-                nom::Err::Error(err) => format!("Error while parsing: {err} ({err:?})"),
-                nom::Err::Failure(fail) => format!("Failed to parse: {fail} ({fail:?})"),
-            },
-        }
-    }
-}
-
 impl From<std::io::Error> for CoreError {
     fn from(value: std::io::Error) -> Self {
         Self::IO {
@@ -52,6 +94,18 @@ impl From<reqwest::Error> for CoreError {
     }
 }
 
+/// Time of day (UTC) of a unix timestamp
+#[cfg(feature = "github")]
+fn retry_time(timestamp: u64) -> String {
+    let seconds = timestamp % (24 * 60 * 60);
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
 #[cfg(feature = "scheduler")]
 impl From<fsrs::FSRSError> for CoreError {
     fn from(value: fsrs::FSRSError) -> Self {
@@ -61,6 +115,13 @@ impl From<fsrs::FSRSError> for CoreError {
     }
 }
 
+#[cfg(feature = "wasm")]
+impl From<CoreError> for wasm_bindgen::JsValue {
+    fn from(value: CoreError) -> Self {
+        wasm_bindgen::JsError::new(&value.to_string()).into()
+    }
+}
+
 pub trait AsCoreError<T> {
     fn context(self, details: Option<&str>) -> Result<T, CoreError>;
 }
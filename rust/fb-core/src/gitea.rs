@@ -0,0 +1,121 @@
+//! Gitea remote, which also works with its forks (Forgejo, Codeberg...)
+
+use reqwest::{
+    Url,
+    blocking::RequestBuilder,
+    header::{AUTHORIZATION, HeaderValue},
+};
+use serde::Deserialize;
+
+use std::sync::Arc;
+
+use crate::{
+    error::CoreError,
+    http::HttpClient,
+    remote::{self, DeckRemote, RefKind},
+};
+
+#[derive(Deserialize)]
+struct Commit {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    commit: BranchCommit,
+}
+
+#[derive(Deserialize)]
+struct BranchCommit {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    commit: Commit,
+}
+
+pub struct Gitea {
+    client: Arc<HttpClient>,
+    base: Url,
+    host: String,
+    repo: String,
+    reference: String,
+    authorization: Option<HeaderValue>,
+}
+
+impl Gitea {
+    /// Repository (`owner/repo`) on the instance at `base_url`
+    pub fn new(
+        base_url: &str,
+        repo: String,
+        reference: String,
+        token: Option<String>,
+    ) -> Result<Self, CoreError> {
+        let (base, host) = remote::base_url(base_url)?;
+
+        Ok(Self {
+            client: HttpClient::shared(),
+            base,
+            host,
+            repo,
+            reference,
+            authorization: token
+                .map(|token| remote::secret(&format!("token {token}")))
+                .transpose()?,
+        })
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        let req = self.client.get(url);
+        remote::authorize(req, AUTHORIZATION, self.authorization.as_ref())
+    }
+
+    /// Url of an endpoint of the repository
+    fn repo_endpoint<'a>(&'a self, segments: impl IntoIterator<Item = &'a str>) -> Url {
+        let repo = ["api", "v1", "repos"]
+            .into_iter()
+            .chain(self.repo.split('/'));
+        remote::endpoint(&self.base, repo.chain(segments))
+    }
+}
+
+impl DeckRemote for Gitea {
+    fn host(&self) -> String {
+        self.host.clone()
+    }
+    fn repo(&self) -> String {
+        self.repo.clone()
+    }
+    fn reference(&self) -> String {
+        self.reference.clone()
+    }
+    fn lookup(&self, kind: RefKind, name: &str) -> Result<Option<String>, CoreError> {
+        let get = |segments: &[&str]| {
+            let url = self.repo_endpoint(segments.iter().copied().chain(name.split('/')));
+            self.get(url)
+        };
+
+        Ok(match kind {
+            RefKind::Branch => {
+                let branch = self.client.json_conditional::<Branch>(get(&["branches"]))?;
+                branch.map(|branch| branch.commit.id)
+            }
+            RefKind::Tag => {
+                let tag = self.client.json_conditional::<Tag>(get(&["tags"]))?;
+                tag.map(|tag| tag.commit.sha)
+            }
+            RefKind::Commit => {
+                let commit = self
+                    .client
+                    .json_conditional::<Commit>(get(&["git", "commits"]))?;
+                commit.map(|commit| commit.sha)
+            }
+        })
+    }
+    fn archive(&self, sha: &str) -> Result<Vec<u8>, CoreError> {
+        let archive = format!("{sha}.tar.gz");
+        self.client
+            .bytes(self.get(self.repo_endpoint(["archive", archive.as_str()])))
+    }
+}
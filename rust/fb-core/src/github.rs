@@ -1,110 +1,232 @@
-//! Thin github api wrapper for the 3 calls I need to do
+//! Github (and Github Enterprise) remote
 
 use reqwest::{
-    blocking::{Client, RequestBuilder},
-    header::{AUTHORIZATION, HeaderValue, USER_AGENT},
+    Url,
+    blocking::RequestBuilder,
+    header::{ACCEPT, AUTHORIZATION, HeaderValue, USER_AGENT},
 };
 use serde::Deserialize;
 
+use std::sync::Arc;
+
+use crate::{
+    error::CoreError,
+    http::HttpClient,
+    remote::{self, DeckRemote, FileChange, RefKind},
+};
+
 #[derive(Deserialize)]
 struct Commit {
     sha: String,
 }
 
+/// A ref, or an annotated tag
+#[derive(Deserialize)]
+struct Ref {
+    object: Object,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    sha: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct Comparison {
+    /// `ahead` when head is a descendant of base
+    status: String,
+    #[serde(default)]
+    files: Vec<ComparedFile>,
+}
+
 #[derive(Deserialize)]
-struct BranchResponse {
-    commit: Commit,
+struct ComparedFile {
+    filename: String,
+    status: String,
+    /// Sha of the blob in head
+    sha: Option<String>,
+    previous_filename: Option<String>,
 }
 
-pub struct GithubAPI {
-    client: Client,
+pub struct Github {
+    client: Arc<HttpClient>,
+    /// Url of the api
+    api: Url,
+    host: String,
     repo: String,
-    token: Option<String>,
+    reference: String,
     username: String,
-    pub sha: String,
+    authorization: Option<HeaderValue>,
 }
 
-impl GithubAPI {
+impl Github {
     const API_VERSION: &'static str = "2022-11-28";
-    pub fn new(
+    /// Past this many changed files, downloading the whole tarball is cheaper than fetching
+    /// each file on its own. This is also well under the 300 files the compare api lists.
+    const MAX_CHANGED_FILES: usize = 100;
+
+    /// Repository on github.com
+    pub fn new(repo: String, reference: String, token: Option<String>) -> Result<Self, CoreError> {
+        let api = Url::parse("https://api.github.com").expect("url is valid");
+        Self::with_api(api, "github.com".to_owned(), repo, reference, token)
+    }
+
+    /// Repository on a Github Enterprise server, given the url of its api
+    /// (`https://host/api/v3`)
+    pub fn enterprise(
+        api_url: &str,
         repo: String,
-        branch: String,
+        reference: String,
         token: Option<String>,
-    ) -> Result<Self, reqwest::Error> {
-        let client = Client::new();
+    ) -> Result<Self, CoreError> {
+        let (api, host) = remote::base_url(api_url)?;
+        Self::with_api(api, host, repo, reference, token)
+    }
 
+    fn with_api(
+        api: Url,
+        host: String,
+        repo: String,
+        reference: String,
+        token: Option<String>,
+    ) -> Result<Self, CoreError> {
         let username = repo
             .split_once('/')
             .map(|(username, _)| username.to_owned())
             .unwrap_or_default();
 
-        let mut req = client
-            .get(format!(
-                "https://api.github.com/repos/{repo}/branches/{branch}"
-            ))
-            .header("X-GitHub-Api-Version", GithubAPI::API_VERSION)
-            .header(USER_AGENT, &username);
-
-        if let Some(token) = token.as_ref() {
-            let mut val = HeaderValue::from_str(&format!("Bearer {token}")).unwrap();
-            val.set_sensitive(true);
-            req = req.header(AUTHORIZATION, val);
-        }
-
-        let res = req.send()?.json::<BranchResponse>()?;
-
         Ok(Self {
-            client,
-            username,
-            token,
+            client: HttpClient::shared(),
+            api,
+            host,
             repo,
-            sha: res.commit.sha,
+            reference,
+            username,
+            authorization: token
+                .map(|token| remote::secret(&format!("Bearer {token}")))
+                .transpose()?,
         })
     }
 
-    fn get(&self, url: String) -> RequestBuilder {
+    fn get(&self, url: Url) -> RequestBuilder {
         let req = self
             .client
             .get(url)
             .header(USER_AGENT, &self.username)
-            .header("X-GitHub-Api-Version", GithubAPI::API_VERSION);
-        if let Some(token) = self.token.as_ref() {
-            let mut val = HeaderValue::from_str(&format!("Bearer {token}")).unwrap();
-            val.set_sensitive(true);
-            req.header(AUTHORIZATION, val)
-        } else {
-            req
-        }
+            .header("X-GitHub-Api-Version", Github::API_VERSION);
+
+        remote::authorize(req, AUTHORIZATION, self.authorization.as_ref())
     }
 
+    /// Url of an endpoint of the repository
+    fn repo_endpoint<'a>(&'a self, segments: impl IntoIterator<Item = &'a str>) -> Url {
+        let repo = std::iter::once("repos").chain(self.repo.split('/'));
+        remote::endpoint(&self.api, repo.chain(segments))
+    }
 
-    pub fn get_tarball(&self) -> Result<reqwest::blocking::Response, reqwest::Error> {
-        self.get(format!(
-            "https://api.github.com/repos/{}/tarball/{}",
-            self.repo, self.sha
-        ))
-        .send()
+    /// Raw content of a blob
+    fn blob(&self, sha: &str) -> Result<Vec<u8>, CoreError> {
+        let url = self.repo_endpoint(["git", "blobs", sha]);
+        self.client.bytes(
+            self.get(url)
+                .header(ACCEPT, "application/vnd.github.raw+json"),
+        )
     }
+}
 
+impl DeckRemote for Github {
+    fn host(&self) -> String {
+        self.host.clone()
+    }
+    fn repo(&self) -> String {
+        self.repo.clone()
+    }
+    fn reference(&self) -> String {
+        self.reference.clone()
+    }
+    fn lookup(&self, kind: RefKind, name: &str) -> Result<Option<String>, CoreError> {
+        let namespace = match kind {
+            RefKind::Branch => "heads",
+            RefKind::Tag => "tags",
+            RefKind::Commit => {
+                let url = self.repo_endpoint(["commits", name]);
+                let commit = self.client.json_conditional::<Commit>(self.get(url))?;
+                return Ok(commit.map(|commit| commit.sha));
+            }
+        };
+
+        let url = self.repo_endpoint(["git", "ref", namespace].into_iter().chain(name.split('/')));
+        let Some(found) = self.client.json_conditional::<Ref>(self.get(url))? else {
+            return Ok(None);
+        };
+        let mut object = found.object;
+
+        // Annotated tags point to a tag object rather than a commit
+        while object.kind == "tag" {
+            let url = self.repo_endpoint(["git", "tags", object.sha.as_str()]);
+            object = self.client.json::<Ref>(self.get(url))?.object;
+        }
+
+        Ok(Some(object.sha))
+    }
+    fn archive(&self, sha: &str) -> Result<Vec<u8>, CoreError> {
+        self.client
+            .bytes(self.get(self.repo_endpoint(["tarball", sha])))
+    }
+    fn changes(&self, base: &str, head: &str) -> Result<Option<Vec<FileChange>>, CoreError> {
+        let basehead = format!("{base}...{head}");
+        let url = self.repo_endpoint(["compare", basehead.as_str()]);
+        let comparison = self.client.json::<Comparison>(self.get(url))?;
+
+        // Anything else means history was rewritten, and base isn't an ancestor of head
+        if !matches!(comparison.status.as_str(), "ahead" | "identical")
+            || comparison.files.len() > Github::MAX_CHANGED_FILES
+        {
+            return Ok(None);
+        }
+
+        let mut changes = Vec::new();
+        for file in comparison.files {
+            if let Some(previous) = file.previous_filename {
+                changes.push(FileChange::Remove { path: previous });
+            }
+
+            match (file.status.as_str(), file.sha) {
+                ("removed", _) => changes.push(FileChange::Remove {
+                    path: file.filename,
+                }),
+                ("unchanged", _) => {}
+                (_, Some(sha)) => changes.push(FileChange::Write {
+                    data: self.blob(&sha)?,
+                    path: file.filename,
+                }),
+                // Nothing to fetch the file from
+                (_, None) => return Ok(None),
+            }
+        }
+
+        Ok(Some(changes))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::remote::stand_in::StandIn;
 
     #[test]
     fn test_authorization_header_is_sensitive() {
-        let client = Client::new();
-        let api = GithubAPI {
-            client,
-            repo: "owner/repo".to_string(),
-            token: Some("secret_token".to_string()),
-            username: "owner".to_string(),
-            sha: "dummy_sha".to_string(),
-        };
+        let api = Github::new(
+            "owner/repo".to_string(),
+            "main".to_string(),
+            Some("secret_token".to_string()),
+        )
+        .unwrap();
 
         let req = api
-            .get("https://example.com".to_string())
+            .get(Url::parse("https://example.com").unwrap())
             .build()
             .expect("Failed to build request");
 
@@ -117,4 +239,87 @@ mod tests {
             panic!("Authorization header missing");
         }
     }
+
+    #[test]
+    fn test_lookup() {
+        let server = StandIn::start();
+        let api = format!("{}/api/v3", server.url);
+        let remote = Github::enterprise(&api, "owner/repo".into(), "2025/fall".into(), None);
+        let remote = remote.unwrap();
+        let object =
+            |sha: &str, kind: &str| format!(r#"{{"object":{{"sha":"{sha}","type":"{kind}"}}}}"#);
+
+        // Annotated tags are followed to their commit
+        server.route(
+            "/api/v3/repos/owner/repo/git/ref/tags/2025/fall",
+            object("t1", "tag"),
+        );
+        server.route(
+            "/api/v3/repos/owner/repo/git/tags/t1",
+            object("c1", "commit"),
+        );
+        let resolved = remote.resolve().unwrap();
+        assert_eq!((resolved.kind, resolved.sha.as_str()), (RefKind::Tag, "c1"));
+
+        server.route(
+            "/api/v3/repos/owner/repo/commits/c1a2",
+            r#"{"sha":"c1a2b3"}"#,
+        );
+        assert_eq!(
+            remote.lookup(RefKind::Commit, "c1a2").unwrap().as_deref(),
+            Some("c1a2b3")
+        );
+        assert_eq!(remote.lookup(RefKind::Branch, "main").unwrap(), None);
+    }
+
+    #[test]
+    fn test_changes() {
+        let server = StandIn::start();
+        let api = format!("{}/api/v3", server.url);
+        let remote = Github::enterprise(&api, "owner/repo".into(), "main".into(), None).unwrap();
+        let compare = |base: &str| format!("/api/v3/repos/owner/repo/compare/{base}...def");
+
+        server.route(
+            &compare("abc"),
+            r#"{"status":"ahead","files":[
+                {"filename":"a.typ","status":"modified","sha":"a1"},
+                {"filename":"b.typ","status":"removed","sha":"b0"},
+                {"filename":"d.typ","status":"renamed","sha":"c1","previous_filename":"c.typ"}
+            ]}"#,
+        );
+        server.route("/api/v3/repos/owner/repo/git/blobs/a1", "A");
+        server.route("/api/v3/repos/owner/repo/git/blobs/c1", "C");
+
+        let write = |path: &str, data: &str| FileChange::Write {
+            path: path.to_owned(),
+            data: data.into(),
+        };
+        let remove = |path: &str| FileChange::Remove {
+            path: path.to_owned(),
+        };
+        assert_eq!(
+            remote.changes("abc", "def").unwrap(),
+            Some(vec![
+                write("a.typ", "A"),
+                remove("b.typ"),
+                remove("c.typ"),
+                write("d.typ", "C")
+            ])
+        );
+        let blob = server.requests().pop().unwrap();
+        assert_eq!(blob.headers["accept"], "application/vnd.github.raw+json");
+
+        server.route(&compare("force"), r#"{"status":"diverged","files":[]}"#);
+        assert_eq!(remote.changes("force", "def").unwrap(), None);
+
+        let file = r#"{"filename":"a.typ","status":"added","sha":"a1"}"#;
+        let files = vec![file; Github::MAX_CHANGED_FILES + 1].join(",");
+        server.route(
+            &compare("big"),
+            format!(r#"{{"status":"ahead","files":[{files}]}}"#),
+        );
+        assert_eq!(remote.changes("big", "def").unwrap(), None);
+
+        assert!(remote.changes("unknown", "def").is_err());
+    }
 }
@@ -0,0 +1,106 @@
+//! Gitlab remote, for gitlab.com and self hosted instances
+
+use reqwest::{
+    Url,
+    blocking::RequestBuilder,
+    header::{HeaderName, HeaderValue},
+};
+use serde::Deserialize;
+
+use std::sync::Arc;
+
+use crate::{
+    error::CoreError,
+    http::HttpClient,
+    remote::{self, DeckRemote, RefKind},
+};
+
+#[derive(Deserialize)]
+struct Commit {
+    id: String,
+}
+
+/// A branch or a tag
+#[derive(Deserialize)]
+struct Ref {
+    commit: Commit,
+}
+
+pub struct Gitlab {
+    client: Arc<HttpClient>,
+    base: Url,
+    host: String,
+    repo: String,
+    reference: String,
+    token: Option<HeaderValue>,
+}
+
+impl Gitlab {
+    pub const PUBLIC_URL: &'static str = "https://gitlab.com";
+
+    /// Repository (`group/subgroup/repo`) on the instance at `base_url`
+    pub fn new(
+        base_url: &str,
+        repo: String,
+        reference: String,
+        token: Option<String>,
+    ) -> Result<Self, CoreError> {
+        let (base, host) = remote::base_url(base_url)?;
+
+        Ok(Self {
+            client: HttpClient::shared(),
+            base,
+            host,
+            repo,
+            reference,
+            token: token.map(|token| remote::secret(&token)).transpose()?,
+        })
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        let req = self.client.get(url);
+        remote::authorize(
+            req,
+            HeaderName::from_static("private-token"),
+            self.token.as_ref(),
+        )
+    }
+
+    /// Url of an endpoint of the repository, projects are identified by their full path
+    fn repository_endpoint<'a>(&'a self, segments: impl IntoIterator<Item = &'a str>) -> Url {
+        let project = ["api", "v4", "projects", self.repo.as_str(), "repository"];
+        remote::endpoint(&self.base, project.into_iter().chain(segments))
+    }
+}
+
+impl DeckRemote for Gitlab {
+    fn host(&self) -> String {
+        self.host.clone()
+    }
+    fn repo(&self) -> String {
+        self.repo.clone()
+    }
+    fn reference(&self) -> String {
+        self.reference.clone()
+    }
+    fn lookup(&self, kind: RefKind, name: &str) -> Result<Option<String>, CoreError> {
+        let refs = match kind {
+            RefKind::Branch => "branches",
+            RefKind::Tag => "tags",
+            RefKind::Commit => {
+                let url = self.repository_endpoint(["commits", name]);
+                let commit = self.client.json_conditional::<Commit>(self.get(url))?;
+                return Ok(commit.map(|commit| commit.id));
+            }
+        };
+
+        let url = self.repository_endpoint([refs, name]);
+        let found = self.client.json_conditional::<Ref>(self.get(url))?;
+        Ok(found.map(|found| found.commit.id))
+    }
+    fn archive(&self, sha: &str) -> Result<Vec<u8>, CoreError> {
+        let mut url = self.repository_endpoint(["archive.tar.gz"]);
+        url.query_pairs_mut().append_pair("sha", sha);
+        self.client.bytes(self.get(url))
+    }
+}
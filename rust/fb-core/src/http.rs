@@ -0,0 +1,405 @@
+//! Http client shared by everything that talks to a server
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, LazyLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use reqwest::{
+    IntoUrl, StatusCode,
+    blocking::{Client, Request, RequestBuilder, Response},
+    header::{ETAG, HeaderMap, HeaderValue, IF_NONE_MATCH, RETRY_AFTER},
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::CoreError;
+
+/// How requests are made
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Longest time to wait for a connection to the server
+    pub connect_timeout: Duration,
+    /// Longest time to wait for the server to send something: the response, or the next part
+    /// of its body when read with [`HttpClient::bytes`] or [`HttpClient::json`]. There is no
+    /// limit on the time a whole download takes, only on how long it can stall.
+    pub read_timeout: Duration,
+    /// How many times a request is retried after failing in a way that might not last
+    /// (timeouts, server errors)
+    pub retries: u32,
+    /// Delay before the first retry, doubled on each one after that
+    pub backoff: Duration,
+    /// Longest delay between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+/// A response kept to answer conditional requests
+struct Tagged {
+    etag: HeaderValue,
+    body: Vec<u8>,
+}
+
+pub struct HttpClient {
+    client: Client,
+    config: HttpConfig,
+    /// Last response to conditional requests, by url
+    tagged: Mutex<HashMap<String, Tagged>>,
+}
+
+static SHARED: LazyLock<Arc<HttpClient>> = LazyLock::new(|| {
+    Arc::new(HttpClient::new(HttpConfig::default()).expect("default config is valid"))
+});
+
+impl HttpClient {
+    pub fn new(config: HttpConfig) -> Result<Self, CoreError> {
+        let client = Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.read_timeout)
+            .build()?;
+
+        Ok(Self {
+            client,
+            config,
+            tagged: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Client with the default config, shared so that connections and the responses kept for
+    /// conditional requests are reused.
+    pub fn shared() -> Arc<Self> {
+        SHARED.clone()
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Send a request, retrying with an exponential backoff when it fails in a way that might
+    /// not last. The response is returned whatever its status, unless the server says the rate
+    /// limit is exceeded.
+    pub fn send(&self, request: RequestBuilder) -> Result<Response, CoreError> {
+        self.retrying(request, |response, _| Ok(Some(response)))
+    }
+
+    /// Send a request and parse the response as json
+    pub fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, CoreError> {
+        self.retrying(request, |response, retry| {
+            let Some(body) = body(response.error_for_status()?, retry)? else {
+                return Ok(None);
+            };
+            parse(&body).map(Some)
+        })
+    }
+
+    /// Send a request and read the response
+    pub fn bytes(&self, request: RequestBuilder) -> Result<Vec<u8>, CoreError> {
+        self.retrying(request, |response, retry| {
+            body(response.error_for_status()?, retry)
+        })
+    }
+
+    /// Like [`Self::bytes`], None if the server has no such resource
+    pub fn bytes_if_found(&self, request: RequestBuilder) -> Result<Option<Vec<u8>>, CoreError> {
+        self.retrying(request, |response, retry| {
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(Some(None));
+            }
+            Ok(body(response.error_for_status()?, retry)?.map(Some))
+        })
+    }
+
+    /// Send a request until `read` gets what it needs from the response. `read` is told
+    /// whether the request can be retried, and returns None to retry it.
+    fn retrying<T>(
+        &self,
+        request: RequestBuilder,
+        read: impl Fn(Response, bool) -> Result<Option<T>, CoreError>,
+    ) -> Result<T, CoreError> {
+        let request = request.build()?;
+        let mut delay = self.config.backoff;
+
+        for attempt in 0.. {
+            let retry = attempt < self.config.retries;
+            match self.client.execute(copy(&request)) {
+                Ok(response) if retry && response.status().is_server_error() => {}
+                Err(err) if retry && (err.is_timeout() || err.is_connect()) => {}
+                Ok(response) => {
+                    if let Some(retry_at) = rate_limit(response.status(), response.headers()) {
+                        return Err(CoreError::RateLimited { retry_at });
+                    }
+                    if let Some(value) = read(response, retry)? {
+                        return Ok(value);
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+
+            log::debug!("Request to {} failed, retrying in {delay:?}", request.url());
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(self.config.max_backoff);
+        }
+
+        unreachable!()
+    }
+
+    /// Like [`Self::json`], but the last response is kept along with its ETag so that the
+    /// server can tell it didn't change instead of sending it again (which also doesn't count
+    /// against the rate limit of most servers). None if the server has no such resource.
+    pub fn json_conditional<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Option<T>, CoreError> {
+        let mut request = request.build()?;
+        let url = request.url().to_string();
+
+        if let Some(tagged) = self.tagged.lock().get(&url) {
+            request
+                .headers_mut()
+                .insert(IF_NONE_MATCH, tagged.etag.clone());
+        }
+
+        let response = self.send(RequestBuilder::from_parts(self.client.clone(), request))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let body = if response.status() == StatusCode::NOT_MODIFIED {
+            match self.tagged.lock().get(&url) {
+                Some(tagged) => tagged.body.clone(),
+                None => {
+                    return Err(CoreError::HTTP {
+                        details: format!("{url} wasn't modified, but nothing was kept"),
+                    });
+                }
+            }
+        } else {
+            let response = response.error_for_status()?;
+            let etag = response.headers().get(ETAG).cloned();
+            let body = read_body(response)?;
+            if let Some(etag) = etag {
+                let tagged = Tagged {
+                    etag,
+                    body: body.clone(),
+                };
+                self.tagged.lock().insert(url, tagged);
+            }
+            body
+        };
+
+        parse(&body).map(Some)
+    }
+}
+
+/// Read the body of a response. The timeout of the client applies to each read rather than to
+/// the whole body (unlike with [`Response::bytes`]), so that large bodies can take as long as
+/// they need as long as the server keeps sending them.
+fn read_body(mut response: Response) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    response.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Read the body of a response, None if the server stopped sending it and the request can be
+/// retried
+fn body(response: Response, retry: bool) -> Result<Option<Vec<u8>>, CoreError> {
+    match read_body(response) {
+        Ok(body) => Ok(Some(body)),
+        Err(err) if retry && is_timeout(&err) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    err.kind() == std::io::ErrorKind::TimedOut
+        || err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<reqwest::Error>())
+            .is_some_and(reqwest::Error::is_timeout)
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, CoreError> {
+    serde_json::from_slice(body).map_err(|err| CoreError::HTTP {
+        details: format!("invalid response: {err}"),
+    })
+}
+
+/// Requests made by [`HttpClient`] have no body (or one in memory), so they can always be copied
+fn copy(request: &Request) -> Request {
+    request.try_clone().expect("request body isn't a stream")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Time (in seconds since the unix epoch) at which requests can be made again, if the response
+/// says the rate limit is exceeded. Github answers with a 403 or a 429, gitlab and gitea with a
+/// 429, and all of them tell when the limit is reset in different headers.
+fn rate_limit(status: StatusCode, headers: &HeaderMap) -> Option<u64> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+
+    let exhausted = header("x-ratelimit-remaining") == Some(0);
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && (exhausted || headers.contains_key(RETRY_AFTER)));
+    if !limited {
+        return None;
+    }
+
+    let retry_at = header(RETRY_AFTER.as_str())
+        .map(|delay| now() + delay)
+        .or_else(|| header("x-ratelimit-reset"))
+        .or_else(|| header("ratelimit-reset"))
+        // Github asks to wait at least a minute when it doesn't say how long
+        .unwrap_or_else(|| now() + 60);
+
+    Some(retry_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::stand_in::{Reply, StandIn};
+
+    fn client() -> HttpClient {
+        HttpClient::new(HttpConfig {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retries() {
+        let server = StandIn::start();
+        let client = client();
+        let url = format!("{}/flaky", server.url);
+
+        server.reply(
+            "/flaky",
+            [Reply::status(503), Reply::status(502), Reply::ok("ok")],
+        );
+        assert_eq!(client.bytes(client.get(&url)).unwrap(), b"ok");
+        assert_eq!(server.requests().len(), 3);
+
+        // Gives up after the last retry
+        server.reply("/flaky", [Reply::status(500)]);
+        assert!(client.bytes(client.get(&url)).is_err());
+        assert_eq!(server.requests().len(), 6);
+
+        // Errors that will happen again aren't retried
+        assert!(
+            client
+                .bytes(client.get(format!("{}/missing", server.url)))
+                .is_err()
+        );
+        assert_eq!(server.requests().len(), 7);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let server = StandIn::start();
+        let client = HttpClient::new(HttpConfig {
+            read_timeout: Duration::from_millis(300),
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let url = |path: &str| format!("{}{path}", server.url);
+
+        // Slower than the read timeout overall, but never stalling for that long
+        let body = vec![b'x'; 1000];
+        server.reply(
+            "/slow",
+            [Reply::ok(body.clone()).slow(5, Duration::from_millis(100))],
+        );
+        assert_eq!(client.bytes(client.get(url("/slow"))).unwrap(), body);
+
+        // Stalls in the middle of the body, then goes through when retried
+        server.reply(
+            "/stalled",
+            [
+                Reply::ok(body.clone()).slow(1, Duration::from_millis(400)),
+                Reply::ok("ok"),
+            ],
+        );
+        assert_eq!(client.bytes(client.get(url("/stalled"))).unwrap(), b"ok");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let server = StandIn::start();
+        let client = client();
+        let get = |path: &str| client.send(client.get(format!("{}{path}", server.url)));
+
+        server.reply(
+            "/github",
+            [Reply::status(403)
+                .header("x-ratelimit-remaining", "0")
+                .header("x-ratelimit-reset", "1700000000")],
+        );
+        assert!(matches!(
+            get("/github"),
+            Err(CoreError::RateLimited {
+                retry_at: 1700000000
+            })
+        ));
+        // Not retried
+        assert_eq!(server.requests().len(), 1);
+
+        server.reply("/gitlab", [Reply::status(429).header("retry-after", "30")]);
+        let Err(CoreError::RateLimited { retry_at }) = get("/gitlab") else {
+            panic!("expected a rate limit error");
+        };
+        assert!(retry_at.abs_diff(now() + 30) <= 1);
+
+        // Forbidden for other reasons
+        server.reply(
+            "/private",
+            [Reply::status(403).header("x-ratelimit-remaining", "10")],
+        );
+        assert_eq!(get("/private").unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_json_conditional() {
+        let server = StandIn::start();
+        let client = client();
+        let url = format!("{}/commit", server.url);
+        let get = || client.json_conditional::<String>(client.get(&url));
+
+        server.reply("/commit", [Reply::ok(r#""abc""#).header("etag", "\"v1\"")]);
+        assert_eq!(get().unwrap().as_deref(), Some("abc"));
+
+        server.reply("/commit", [Reply::status(304)]);
+        assert_eq!(get().unwrap().as_deref(), Some("abc"));
+        assert_eq!(server.requests()[1].headers["if-none-match"], "\"v1\"");
+
+        server.reply("/commit", [Reply::status(404)]);
+        assert_eq!(get().unwrap(), None);
+    }
+}
@@ -1,22 +1,43 @@
+#[cfg(feature = "cache")]
+pub mod cache;
 pub mod cards;
+mod codegen;
+#[cfg(feature = "compile")]
+pub mod diagnostics;
+#[cfg(feature = "compile")]
+pub mod diff;
+pub mod directives;
 pub mod error;
 #[cfg(feature = "fuzzy")]
 pub mod fuzzy;
+#[cfg(feature = "github")]
+pub mod gitea;
+#[cfg(feature = "github")]
+pub mod github;
+#[cfg(feature = "github")]
+pub mod gitlab;
+#[cfg(feature = "github")]
+pub mod http;
 #[cfg(feature = "compile")]
-pub mod world;
+pub mod manifest;
 #[cfg(feature = "compile")]
 pub mod packages;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+#[cfg(feature = "github")]
+pub mod remote;
+#[cfg(feature = "compile")]
+pub mod render;
 #[cfg(feature = "scheduler")]
 pub mod scheduler;
-#[cfg(feature = "github")]
-mod github;
-#[cfg(feature = "cache")]
-pub mod cache;
-
+pub mod source_map;
 #[cfg(feature = "compile")]
-pub use typst;
+pub mod world;
+
 #[cfg(feature = "scheduler")]
 pub use fsrs;
+#[cfg(feature = "compile")]
+pub use typst;
 
 #[cfg(feature = "uniffi")]
 uniffi::setup_scaffolding!();
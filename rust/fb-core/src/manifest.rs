@@ -0,0 +1,188 @@
+//! Deck manifest: an optional `flashbang.toml` at the root of a deck that tells how it is
+//! loaded.
+//!
+//! ```toml
+//! name = "Linear algebra"
+//! description = "Cards of the first semester"
+//! version = "2025.1"
+//! min-flashbang-version = "0.1.0"
+//! # Globs (relative to the deck root) of the files cards are looked for in, every typst file
+//! # when not given
+//! cards = ["chapters/**/*.typ"]
+//! exclude = ["drafts"]
+//! # Directories assets are loaded from, anywhere when not given
+//! assets = ["figures"]
+//! packages = ["@preview/cetz:0.4.0"]
+//!
+//! [config]
+//! text-size = 14
+//! ```
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+use typst_syntax::package::{PackageSpec, PackageVersion};
+
+use crate::{cards::SourceConfig, error::CoreError};
+
+/// Name of the manifest file, at the root of the deck
+pub const MANIFEST_FILE: &str = "flashbang.toml";
+
+/// Version of flashbang, compared to the minimum version asked by decks
+pub const FLASHBANG_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Content of a `flashbang.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct DeckManifest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Version of the deck, in whatever format its authors like
+    pub version: Option<String>,
+    /// Oldest version of flashbang that can load the deck
+    pub min_flashbang_version: Option<String>,
+    /// Globs of the typst files cards are looked for in, every file when empty
+    pub cards: Vec<String>,
+    /// Globs of the paths to skip, excluding a directory excludes everything in it
+    pub exclude: Vec<String>,
+    /// Globs of the directories (or files) assets are loaded from, anywhere when empty
+    pub assets: Vec<String>,
+    /// Typst packages used by the deck (`@preview/name:version`), fetched when it is loaded
+    pub packages: Vec<String>,
+    /// Defaults for the config the cards are compiled with
+    pub config: ConfigOverrides,
+}
+
+/// Fields of a [`SourceConfig`] set by a deck, the others are left as they are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct ConfigOverrides {
+    pub page_width: Option<u32>,
+    pub text_size: Option<u32>,
+    pub text_color: Option<u32>,
+    pub sans_math: Option<bool>,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: SourceConfig) -> SourceConfig {
+        SourceConfig {
+            page_width: self.page_width.unwrap_or(config.page_width),
+            text_size: self.text_size.unwrap_or(config.text_size),
+            text_color: self.text_color.unwrap_or(config.text_color),
+            sans_math: self.sans_math.unwrap_or(config.sans_math),
+        }
+    }
+}
+
+impl DeckManifest {
+    /// Parse a manifest, failing if the deck needs a newer version of flashbang
+    pub fn parse(content: &str) -> Result<Self, CoreError> {
+        let invalid = |details: String| CoreError::Other {
+            details: format!("invalid {MANIFEST_FILE}: {details}"),
+        };
+
+        // The version is checked first, newer decks may use fields that aren't known yet
+        let table = toml::Table::from_str(content).map_err(|err| invalid(err.to_string()))?;
+        if let Some(min) = table
+            .get("min-flashbang-version")
+            .and_then(|min| min.as_str())
+        {
+            check_version(min).map_err(|err| match err {
+                VersionError::Invalid(details) => invalid(details),
+                VersionError::TooOld(details) => CoreError::Other { details },
+            })?;
+        }
+
+        let manifest = Self::deserialize(table).map_err(|err| invalid(err.to_string()))?;
+        manifest.package_specs().map_err(invalid)?;
+
+        Ok(manifest)
+    }
+
+    /// Packages used by the deck
+    pub fn package_specs(&self) -> Result<Vec<PackageSpec>, String> {
+        self.packages
+            .iter()
+            .map(|package| {
+                PackageSpec::from_str(package)
+                    .map_err(|err| format!("invalid package \"{package}\": {err}"))
+            })
+            .collect()
+    }
+}
+
+enum VersionError {
+    Invalid(String),
+    TooOld(String),
+}
+
+fn check_version(min: &str) -> Result<(), VersionError> {
+    let parse = |version: &str| {
+        PackageVersion::from_str(version)
+            .map_err(|err| VersionError::Invalid(format!("invalid version \"{version}\": {err}")))
+    };
+
+    if parse(FLASHBANG_VERSION)? < parse(min)? {
+        return Err(VersionError::TooOld(format!(
+            "the deck needs flashbang {min} or newer, this is {FLASHBANG_VERSION}"
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = DeckManifest::parse(
+            r#"
+            name = "Linear algebra"
+            min-flashbang-version = "0.0.1"
+            cards = ["chapters/**/*.typ"]
+            packages = ["@preview/cetz:0.4.0"]
+
+            [config]
+            text-size = 14
+            text-color = 0xff0000
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name.as_deref(), Some("Linear algebra"));
+        assert_eq!(manifest.cards, ["chapters/**/*.typ"]);
+        assert!(manifest.exclude.is_empty());
+        assert_eq!(manifest.package_specs().unwrap()[0].name, "cetz");
+
+        let config = SourceConfig {
+            page_width: 300,
+            text_size: 12,
+            text_color: 0,
+            sans_math: false,
+        };
+        let config = manifest.config.apply(config);
+        assert_eq!((config.page_width, config.text_size), (300, 14));
+        assert_eq!(config.text_color, 0xff0000);
+
+        assert_eq!(DeckManifest::parse("").unwrap(), DeckManifest::default());
+        assert!(DeckManifest::parse("nmae = \"typo\"").is_err());
+        assert!(DeckManifest::parse("packages = [\"cetz\"]").is_err());
+        assert!(DeckManifest::parse("[config]\ntext-size = \"big\"").is_err());
+    }
+
+    #[test]
+    fn test_min_version() {
+        let Err(CoreError::Other { details }) =
+            DeckManifest::parse("min-flashbang-version = \"999.0.0\"\nfuture-field = true")
+        else {
+            panic!("expected the deck to be refused");
+        };
+        assert!(details.contains("needs flashbang 999.0.0"), "{details}");
+
+        assert!(DeckManifest::parse("min-flashbang-version = \"one\"").is_err());
+    }
+}
@@ -0,0 +1,405 @@
+//! Export of compiled cards to pdf, laid out for printing.
+
+use std::sync::Arc;
+
+use itertools::Itertools;
+use typst::{
+    foundations::{Content, Smart},
+    introspection::Introspector,
+    layout::{Abs, Frame, FrameItem, Page, PagedDocument, Point, Ratio, Size, Transform},
+    model::DocumentInfo,
+    syntax::Span,
+    visualize::{Color, FixedStroke, Geometry, Paint},
+};
+use typst_pdf::PdfOptions as TypstPdfOptions;
+
+use crate::{
+    error::CoreError,
+    world::{CardPage, CompiledCard},
+};
+
+/// Space between the pages of a card, and around the separator of the question and answer
+const GAP: f64 = 8.0;
+/// Space kept between the cards and the cut lines of a grid
+const CELL_PADDING: f64 = 8.0;
+
+/// How cards are laid out on the pages of the pdf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum PdfLayout {
+    /// Each card on its own page, the question above the answer
+    OnePerPage,
+    /// Questions on left pages, and their answer on the right page facing them. The document
+    /// starts with a blank page so that this holds when viewed or printed as spreads.
+    Facing,
+    /// Grids of questions on the front of each sheet, and the answers on the back, placed so
+    /// that they end up behind their question when printed duplex (flipped on the long edge).
+    /// Cut lines are drawn on both sides.
+    Grid { columns: u32, rows: u32 },
+}
+
+/// Options of the pdf export, lengths are in pt
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct PdfOptions {
+    pub layout: PdfLayout,
+    pub paper_width: f64,
+    pub paper_height: f64,
+    pub margin: f64,
+}
+
+impl Default for PdfOptions {
+    /// One card per page on A4 paper
+    fn default() -> Self {
+        Self {
+            layout: PdfLayout::OnePerPage,
+            paper_width: 595.28,
+            paper_height: 841.89,
+            margin: 36.0,
+        }
+    }
+}
+
+/// Lay out compiled cards and export them to pdf. Cards are scaled down when they don't fit
+/// the space they are given, but never scaled up.
+pub fn export(cards: &[CompiledCard], options: &PdfOptions) -> Result<Vec<u8>, CoreError> {
+    let document = PagedDocument {
+        pages: layout(cards, options)?.into_iter().map(page).collect(),
+        info: DocumentInfo::default(),
+        introspector: Introspector::default(),
+    };
+
+    // The pages are assembled from different documents, so there is no tag tree to speak of
+    let options = TypstPdfOptions {
+        tagged: false,
+        ..Default::default()
+    };
+
+    typst_pdf::pdf(&document, &options).map_err(|errors| CoreError::Other {
+        details: errors.iter().map(|error| error.message.as_str()).join("\n"),
+    })
+}
+
+/// Pages of the pdf
+fn layout(cards: &[CompiledCard], options: &PdfOptions) -> Result<Vec<Frame>, CoreError> {
+    let invalid = |details: &str| CoreError::Other {
+        details: format!("invalid pdf options: {details}"),
+    };
+
+    if cards.is_empty() {
+        return Err(CoreError::Other {
+            details: "no cards to export".to_owned(),
+        });
+    }
+    if options.margin < 0.0
+        || options.paper_width <= 2.0 * options.margin
+        || options.paper_height <= 2.0 * options.margin
+    {
+        return Err(invalid("the margins don't leave any space on the paper"));
+    }
+
+    let sheet = Sheet {
+        size: Size::new(Abs::pt(options.paper_width), Abs::pt(options.paper_height)),
+        margin: Abs::pt(options.margin),
+    };
+
+    let frames = match options.layout {
+        PdfLayout::OnePerPage => cards
+            .iter()
+            .map(|card| {
+                let question = stack(side(&card.question));
+                let answer = stack(side(&card.answer));
+                let width = question.width().max(answer.width());
+
+                sheet.page_with(stack([question, separator(width), answer]))
+            })
+            .collect_vec(),
+        PdfLayout::Facing => std::iter::once(sheet.page())
+            .chain(cards.iter().flat_map(|card| {
+                [&card.question, &card.answer].map(|pages| sheet.page_with(stack(side(pages))))
+            }))
+            .collect_vec(),
+        PdfLayout::Grid { columns, rows } => {
+            if columns == 0 || rows == 0 {
+                return Err(invalid("the grid needs at least one row and one column"));
+            }
+
+            grid(&sheet, cards, columns as usize, rows as usize)
+        }
+    };
+
+    Ok(frames)
+}
+
+/// Front and back pages of each sheet of a grid layout
+fn grid(sheet: &Sheet, cards: &[CompiledCard], columns: usize, rows: usize) -> Vec<Frame> {
+    let content = sheet.content_size();
+    let cell = Size::new(content.x / columns as f64, content.y / rows as f64);
+    let padding = Abs::pt(CELL_PADDING);
+    let inner = Size::new(cell.x - 2.0 * padding, cell.y - 2.0 * padding).max(Size::zero());
+
+    cards
+        .chunks(columns * rows)
+        .flat_map(|chunk| {
+            let mut front = sheet.page();
+            let mut back = sheet.page();
+
+            for (i, card) in chunk.iter().enumerate() {
+                let (column, row) = (i % columns, i / columns);
+                // Flipping the sheet on its long edge mirrors the columns
+                let mirrored = columns - 1 - column;
+
+                for (page, column, pages) in [
+                    (&mut front, column, &card.question),
+                    (&mut back, mirrored, &card.answer),
+                ] {
+                    let frame = fit(stack(side(pages)), inner);
+                    let origin = sheet.content_origin()
+                        + Point::new(cell.x * column as f64, cell.y * row as f64)
+                        + Point::new(
+                            (cell.x - frame.width()) / 2.0,
+                            (cell.y - frame.height()) / 2.0,
+                        );
+                    page.push_frame(origin, frame);
+                }
+            }
+
+            for page in [&mut front, &mut back] {
+                sheet.cut_lines(page, columns, rows);
+            }
+
+            [front, back]
+        })
+        .collect()
+}
+
+/// Paper the cards are laid out on
+struct Sheet {
+    size: Size,
+    margin: Abs,
+}
+
+impl Sheet {
+    fn page(&self) -> Frame {
+        Frame::hard(self.size)
+    }
+
+    /// Page with content at the top, centered horizontally
+    fn page_with(&self, content: Frame) -> Frame {
+        let content = fit(content, self.content_size());
+        let x = (self.content_size().x - content.width()) / 2.0;
+
+        let mut page = self.page();
+        page.push_frame(self.content_origin() + Point::with_x(x), content);
+        page
+    }
+
+    fn content_origin(&self) -> Point {
+        Point::splat(self.margin)
+    }
+
+    fn content_size(&self) -> Size {
+        self.size - Size::splat(2.0 * self.margin)
+    }
+
+    /// Lines along the borders of the cells of a grid, across the whole page
+    fn cut_lines(&self, page: &mut Frame, columns: usize, rows: usize) {
+        let content = self.content_size();
+        let stroke = FixedStroke::from_pair(
+            Paint::Solid(Color::from_u8(160, 160, 160, 255)),
+            Abs::pt(0.5),
+        );
+
+        for column in 0..=columns {
+            let x = self.margin + content.x * (column as f64 / columns as f64);
+            let line = Geometry::Line(Point::with_y(self.size.y)).stroked(stroke.clone());
+            page.push(Point::with_x(x), FrameItem::Shape(line, Span::detached()));
+        }
+        for row in 0..=rows {
+            let y = self.margin + content.y * (row as f64 / rows as f64);
+            let line = Geometry::Line(Point::with_x(self.size.x)).stroked(stroke.clone());
+            page.push(Point::with_y(y), FrameItem::Shape(line, Span::detached()));
+        }
+    }
+}
+
+/// Frames of the pages of one side of a card
+fn side(pages: &[Arc<CardPage>]) -> Vec<Frame> {
+    pages.iter().map(|page| page.frame().clone()).collect()
+}
+
+/// Stack frames on top of each other, centered horizontally
+fn stack(frames: impl IntoIterator<Item = Frame>) -> Frame {
+    let frames = frames.into_iter().collect_vec();
+    let gap = Abs::pt(GAP);
+    let width = frames
+        .iter()
+        .fold(Abs::zero(), |width, frame| width.max(frame.width()));
+    let height =
+        frames.iter().map(Frame::height).sum::<Abs>() + gap * frames.len().saturating_sub(1) as f64;
+
+    let mut stack = Frame::hard(Size::new(width, height));
+    let mut y = Abs::zero();
+    for frame in frames {
+        let frame_height = frame.height();
+        stack.push_frame(Point::new((width - frame.width()) / 2.0, y), frame);
+        y += frame_height + gap;
+    }
+
+    stack
+}
+
+/// Horizontal line between the question and the answer
+fn separator(width: Abs) -> Frame {
+    let mut frame = Frame::hard(Size::with_x(width));
+    let line = Geometry::Line(Point::with_x(width)).stroked(FixedStroke::from_pair(
+        Paint::Solid(Color::BLACK),
+        Abs::pt(0.5),
+    ));
+    frame.push(Point::zero(), FrameItem::Shape(line, Span::detached()));
+    frame
+}
+
+/// Scale a frame down so that it fits in the given size
+fn fit(mut frame: Frame, size: Size) -> Frame {
+    let scale = [1.0, size.x / frame.width(), size.y / frame.height()]
+        .into_iter()
+        .filter(|scale| scale.is_finite())
+        .fold(1.0, f64::min)
+        .max(0.0);
+
+    if scale < 1.0 {
+        let scaled = frame.size() * scale;
+        frame.transform(Transform::scale(Ratio::new(scale), Ratio::new(scale)));
+        frame.set_size(scaled);
+    }
+
+    frame
+}
+
+fn page(frame: Frame) -> Page {
+    Page {
+        frame,
+        fill: Smart::Auto,
+        numbering: None,
+        supplement: Content::empty(),
+        number: 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::compile;
+
+    fn cards(count: usize) -> Vec<CompiledCard> {
+        let content = (0..count)
+            .map(|i| format!("#card(\"{i}\", \"{i}\", ())\nQuestion {i}\n#answer\nAnswer\n"))
+            .join("");
+        compile(&content).unwrap().cards
+    }
+
+    fn options(layout: PdfLayout) -> PdfOptions {
+        PdfOptions {
+            layout,
+            ..Default::default()
+        }
+    }
+
+    /// Positions of the cards put on a page
+    fn cards_on(page: &Frame) -> Vec<Point> {
+        page.items()
+            .filter(|(_, item)| matches!(item, FrameItem::Group(_)))
+            .map(|(position, _)| *position)
+            .collect()
+    }
+
+    /// Number of lines drawn on a page
+    fn lines_on(page: &Frame) -> usize {
+        page.items()
+            .filter(|(_, item)| matches!(item, FrameItem::Shape(..)))
+            .count()
+    }
+
+    #[test]
+    fn test_one_per_page() {
+        let options = options(PdfLayout::OnePerPage);
+        let pages = layout(&cards(5), &options).unwrap();
+
+        assert_eq!(pages.len(), 5);
+        for page in &pages {
+            assert_eq!(page.size(), Size::new(Abs::pt(595.28), Abs::pt(841.89)));
+            let [position] = cards_on(page)[..] else {
+                panic!("expected a single card on the page");
+            };
+            // At the top of the page, centered horizontally
+            assert_eq!(position.y, Abs::pt(options.margin));
+            assert!(position.x > Abs::pt(options.margin));
+        }
+    }
+
+    #[test]
+    fn test_facing() {
+        let pages = layout(&cards(5), &options(PdfLayout::Facing)).unwrap();
+
+        // A blank page, then the question and the answer of each card
+        assert_eq!(pages.len(), 11);
+        assert!(pages[0].is_empty());
+        assert!(pages[1..].iter().all(|page| cards_on(page).len() == 1));
+    }
+
+    #[test]
+    fn test_grid() {
+        let options = options(PdfLayout::Grid {
+            columns: 2,
+            rows: 2,
+        });
+        let pages = layout(&cards(5), &options).unwrap();
+
+        // Front and back of two sheets, the second one with a single card
+        assert_eq!(pages.len(), 4);
+        let counts = pages.iter().map(|page| cards_on(page).len()).collect_vec();
+        assert_eq!(counts, [4, 4, 1, 1]);
+        // Cut lines around the 2x2 cells on both sides
+        assert!(pages.iter().all(|page| lines_on(page) == 6));
+
+        // The answer is behind its question once the sheet is flipped on its long edge
+        let middle = Abs::pt(options.paper_width / 2.0);
+        let (question, answer) = (cards_on(&pages[2])[0], cards_on(&pages[3])[0]);
+        assert!(question.x < middle && answer.x > middle);
+        assert_eq!(question.y, answer.y);
+    }
+
+    #[test]
+    fn test_export() {
+        let cards = cards(2);
+        for layout in [
+            PdfLayout::OnePerPage,
+            PdfLayout::Facing,
+            PdfLayout::Grid {
+                columns: 2,
+                rows: 4,
+            },
+        ] {
+            let pdf = export(&cards, &options(layout)).unwrap();
+            assert!(pdf.starts_with(b"%PDF"));
+        }
+
+        assert!(
+            export(
+                &cards,
+                &options(PdfLayout::Grid {
+                    columns: 0,
+                    rows: 2
+                })
+            )
+            .is_err()
+        );
+        assert!(export(&[], &PdfOptions::default()).is_err());
+        let options = PdfOptions {
+            margin: 400.0,
+            ..Default::default()
+        };
+        assert!(export(&cards, &options).is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! Git hosting services decks can be synced from
+
+#[cfg(feature = "cache")]
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Read,
+    path::PathBuf,
+};
+
+#[cfg(feature = "cache")]
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use reqwest::{
+    Url,
+    blocking::RequestBuilder,
+    header::{HeaderName, HeaderValue},
+};
+
+use crate::{error::CoreError, gitea::Gitea, github::Github, gitlab::Gitlab};
+
+/// A repository on a git hosting service, at a given ref
+pub trait DeckRemote {
+    /// Host of the service (`github.com`, ...), used to tell decks apart in the cache
+    fn host(&self) -> String;
+    /// Repository (`owner/repo`)
+    fn repo(&self) -> String;
+    /// Ref spec the deck is synced from, see [`resolve_ref`]
+    fn reference(&self) -> String;
+    /// Sha of the commit a ref of the given kind points to, None if there is no such ref.
+    /// Commits can be given by a short sha.
+    fn lookup(&self, kind: RefKind, name: &str) -> Result<Option<String>, CoreError>;
+    /// Commit the ref spec currently points to
+    fn resolve(&self) -> Result<ResolvedRef, CoreError> {
+        resolve_ref(&self.reference(), |kind, name| self.lookup(kind, name))
+    }
+    /// Gzipped tarball of the repository at a commit, with everything in a top level
+    /// directory
+    fn archive(&self, sha: &str) -> Result<Vec<u8>, CoreError>;
+    /// Files that changed between two commits, with their content in `head`. None when the
+    /// remote can't tell (or it would be slower than downloading the whole archive), for
+    /// example when `base` isn't an ancestor of `head` anymore.
+    fn changes(&self, base: &str, head: &str) -> Result<Option<Vec<FileChange>>, CoreError> {
+        let _ = (base, head);
+        Ok(None)
+    }
+}
+
+/// What a ref spec points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum RefKind {
+    /// Moves with new commits
+    Branch,
+    /// Usually stays on a release
+    Tag,
+    /// Never changes
+    Commit,
+}
+
+/// A ref spec resolved to a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct ResolvedRef {
+    pub sha: String,
+    pub kind: RefKind,
+}
+
+/// Resolve a ref spec: `refs/heads/name` and `refs/tags/name` are a branch and a tag, anything
+/// else is looked up as a branch, then as a tag, and then as a full or short commit sha.
+pub fn resolve_ref(
+    spec: &str,
+    lookup: impl Fn(RefKind, &str) -> Result<Option<String>, CoreError>,
+) -> Result<ResolvedRef, CoreError> {
+    let is_sha = (4..=40).contains(&spec.len()) && spec.bytes().all(|b| b.is_ascii_hexdigit());
+    let candidates = if let Some(branch) = spec.strip_prefix("refs/heads/") {
+        vec![(RefKind::Branch, branch)]
+    } else if let Some(tag) = spec.strip_prefix("refs/tags/") {
+        vec![(RefKind::Tag, tag)]
+    } else if is_sha {
+        vec![
+            (RefKind::Branch, spec),
+            (RefKind::Tag, spec),
+            (RefKind::Commit, spec),
+        ]
+    } else {
+        vec![(RefKind::Branch, spec), (RefKind::Tag, spec)]
+    };
+
+    for (kind, name) in candidates {
+        if let Some(sha) = lookup(kind, name)? {
+            return Ok(ResolvedRef { sha, kind });
+        }
+    }
+
+    Err(CoreError::Other {
+        details: format!("no branch, tag or commit matches \"{spec}\""),
+    })
+}
+
+/// A file that changed between two commits, paths are relative to the root of the repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    /// The file was added or modified
+    Write {
+        path: String,
+        data: Vec<u8>,
+    },
+    Remove {
+        path: String,
+    },
+}
+
+/// Git hosting services with a [`DeckRemote`] implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum RemoteKind {
+    Github,
+    Gitlab,
+    /// Gitea and its forks (Forgejo, Codeberg...)
+    Gitea,
+}
+
+/// Where to sync a deck from
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct RemoteConfig {
+    pub kind: RemoteKind,
+    /// Url of the server, github.com and gitlab.com when not given. Github Enterprise servers
+    /// are given by the url of their api (`https://host/api/v3`).
+    pub base_url: Option<String>,
+    /// Repository (`owner/repo`, or `group/subgroup/repo` on gitlab)
+    pub repo: String,
+    /// Branch, tag or commit
+    pub reference: String,
+    pub token: Option<String>,
+}
+
+impl RemoteConfig {
+    pub fn remote(&self) -> Result<Box<dyn DeckRemote>, CoreError> {
+        let (repo, reference, token) = (
+            self.repo.clone(),
+            self.reference.clone(),
+            self.token.clone(),
+        );
+
+        Ok(match (self.kind, &self.base_url) {
+            (RemoteKind::Github, None) => Box::new(Github::new(repo, reference, token)?),
+            (RemoteKind::Github, Some(url)) => {
+                Box::new(Github::enterprise(url, repo, reference, token)?)
+            }
+            (RemoteKind::Gitlab, url) => Box::new(Gitlab::new(
+                url.as_deref().unwrap_or(Gitlab::PUBLIC_URL),
+                repo,
+                reference,
+                token,
+            )?),
+            (RemoteKind::Gitea, Some(url)) => Box::new(Gitea::new(url, repo, reference, token)?),
+            (RemoteKind::Gitea, None) => {
+                return Err(CoreError::Other {
+                    details: "gitea remotes need the url of the server".to_owned(),
+                });
+            }
+        })
+    }
+}
+
+/// Parse the url of a server, returning it along with its host (and port if any)
+pub(crate) fn base_url(url: &str) -> Result<(Url, String), CoreError> {
+    let invalid = |details: &str| CoreError::Other {
+        details: format!("invalid server url \"{url}\": {details}"),
+    };
+
+    let url = Url::parse(url).map_err(|err| invalid(&err.to_string()))?;
+    if url.cannot_be_a_base() {
+        return Err(invalid("not a base url"));
+    }
+
+    let host = url.host_str().ok_or_else(|| invalid("no host"))?;
+    let host = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    };
+
+    Ok((url, host))
+}
+
+/// Url of an endpoint of a server, each segment is percent encoded (`/` included)
+pub(crate) fn endpoint<'a>(base: &Url, segments: impl IntoIterator<Item = &'a str>) -> Url {
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .expect("base urls are checked when parsed")
+        .pop_if_empty()
+        .extend(segments);
+    url
+}
+
+/// Header holding a token, marked as sensitive so that it doesn't show up in logs
+pub(crate) fn secret(value: &str) -> Result<HeaderValue, CoreError> {
+    let mut value = HeaderValue::from_str(value).map_err(|_| CoreError::Other {
+        details: "the token contains characters that can't be sent".to_owned(),
+    })?;
+    value.set_sensitive(true);
+
+    Ok(value)
+}
+
+/// Add the authorization header of a remote to a request, if it has a token
+pub(crate) fn authorize(
+    request: RequestBuilder,
+    header: HeaderName,
+    value: Option<&HeaderValue>,
+) -> RequestBuilder {
+    match value {
+        Some(value) => request.header(header, value.clone()),
+        None => request,
+    }
+}
+
+/// Apply changes to a tarball made by [`DeckRemote::archive`], new files are added to its top
+/// level directory. Only regular files are kept.
+#[cfg(feature = "cache")]
+pub(crate) fn patch_tarball(
+    tarball: impl Read,
+    changes: Vec<FileChange>,
+) -> Result<Vec<u8>, CoreError> {
+    let mut writes = BTreeMap::new();
+    let mut removed = HashSet::new();
+    for change in changes {
+        match change {
+            FileChange::Write { path, data } => {
+                removed.remove(&path);
+                writes.insert(path, data);
+            }
+            FileChange::Remove { path } => {
+                writes.remove(&path);
+                removed.insert(path);
+            }
+        }
+    }
+
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut root = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        let top = PathBuf::from(components.next().map(|c| c.as_os_str()).unwrap_or_default());
+        let relative = components.as_path().to_string_lossy().into_owned();
+        root.get_or_insert(top);
+
+        if removed.contains(&relative) {
+            continue;
+        }
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry.header().mode().unwrap_or(0o644));
+        match writes.remove(&relative) {
+            Some(data) => {
+                header.set_size(data.len() as u64);
+                builder.append_data(&mut header, &path, data.as_slice())?;
+            }
+            None => {
+                header.set_size(entry.size());
+                builder.append_data(&mut header, &path, &mut entry)?;
+            }
+        }
+    }
+
+    let root = root.unwrap_or_else(|| PathBuf::from("deck"));
+    for (path, data) in writes {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, root.join(path), data.as_slice())?;
+    }
+
+    Ok(builder.into_inner()?.finish()?)
+}
+
+/// A local http server standing in for git hosting services in tests
+#[cfg(test)]
+pub(crate) mod stand_in {
+    use std::{
+        collections::{HashMap, VecDeque},
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::Arc,
+        time::Duration,
+    };
+
+    use parking_lot::Mutex;
+
+    /// A request received by the server
+    #[derive(Debug, Clone)]
+    pub struct Request {
+        /// Path and query, as sent
+        pub target: String,
+        /// Headers, with lowercase names
+        pub headers: HashMap<String, String>,
+    }
+
+    /// A response of the server
+    #[derive(Debug, Clone)]
+    pub struct Reply {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+        /// Parts the body is sent in, and the time waited before each of them
+        chunks: usize,
+        delay: Duration,
+    }
+
+    impl Reply {
+        pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+            Self {
+                status: 200,
+                headers: Vec::new(),
+                body: body.into(),
+                chunks: 1,
+                delay: Duration::ZERO,
+            }
+        }
+
+        pub fn status(status: u16) -> Self {
+            Self {
+                status,
+                ..Self::ok("")
+            }
+        }
+
+        pub fn header(mut self, name: &str, value: &str) -> Self {
+            self.headers.push((name.to_owned(), value.to_owned()));
+            self
+        }
+
+        /// Send the body in `chunks` parts, waiting `delay` before each of them
+        pub fn slow(mut self, chunks: usize, delay: Duration) -> Self {
+            self.chunks = chunks.max(1);
+            self.delay = delay;
+            self
+        }
+    }
+
+    /// Serves fixed responses by path (and query), anything else is a 404
+    pub struct StandIn {
+        pub url: String,
+        routes: Arc<Mutex<HashMap<String, VecDeque<Reply>>>>,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl StandIn {
+        pub fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let routes = Arc::new(Mutex::new(HashMap::<String, VecDeque<Reply>>::new()));
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let (served, received) = (routes.clone(), requests.clone());
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else { continue };
+                    let mut reader = BufReader::new(&mut stream);
+
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap_or_default();
+                    let target = line.split(' ').nth(1).unwrap_or_default().to_owned();
+
+                    let mut headers = HashMap::new();
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or_default() == 0 {
+                            break;
+                        }
+                        let Some((name, value)) = line.trim_end().split_once(':') else {
+                            break;
+                        };
+                        headers.insert(name.to_lowercase(), value.trim().to_owned());
+                    }
+
+                    // Replies are given in order, the last one is kept for later requests
+                    let reply =
+                        served
+                            .lock()
+                            .get_mut(&target)
+                            .and_then(|replies| match replies.len() {
+                                1 => replies.front().cloned(),
+                                _ => replies.pop_front(),
+                            });
+                    received.lock().push(Request { target, headers });
+
+                    let reply = reply.unwrap_or_else(|| Reply {
+                        body: b"not found".to_vec(),
+                        ..Reply::status(404)
+                    });
+                    let mut head = format!(
+                        "HTTP/1.1 {} Stand-In\r\nContent-Length: {}\r\nConnection: close\r\n",
+                        reply.status,
+                        reply.body.len()
+                    );
+                    for (name, value) in &reply.headers {
+                        head.push_str(&format!("{name}: {value}\r\n"));
+                    }
+                    head.push_str("\r\n");
+                    let _ = stream.write_all(head.as_bytes());
+                    let _ = stream.flush();
+                    let size = reply.body.len().div_ceil(reply.chunks).max(1);
+                    for chunk in reply.body.chunks(size) {
+                        std::thread::sleep(reply.delay);
+                        let _ = stream.write_all(chunk);
+                        let _ = stream.flush();
+                    }
+                }
+            });
+
+            Self {
+                url,
+                routes,
+                requests,
+            }
+        }
+
+        /// Answer requests to `target` with `body`
+        pub fn route(&self, target: &str, body: impl Into<Vec<u8>>) {
+            self.reply(target, [Reply::ok(body)]);
+        }
+
+        /// Answer requests to `target` with each reply in turn, the last one being repeated
+        pub fn reply(&self, target: &str, replies: impl IntoIterator<Item = Reply>) {
+            self.routes
+                .lock()
+                .insert(target.to_owned(), replies.into_iter().collect());
+        }
+
+        pub fn requests(&self) -> Vec<Request> {
+            self.requests.lock().clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stand_in::StandIn, *};
+
+    fn remote(kind: RemoteKind, url: &str, repo: &str, reference: &str) -> Box<dyn DeckRemote> {
+        RemoteConfig {
+            kind,
+            base_url: Some(url.to_owned()),
+            repo: repo.to_owned(),
+            reference: reference.to_owned(),
+            token: Some("secret".to_owned()),
+        }
+        .remote()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_ref() {
+        let refs = [
+            (RefKind::Branch, "main", "b1"),
+            (RefKind::Tag, "v1", "t1"),
+            (RefKind::Tag, "main", "t2"),
+            (RefKind::Commit, "abcd", "abcdef"),
+        ];
+        let resolve = |spec| {
+            resolve_ref(spec, |kind, name| {
+                let found = refs.iter().find(|(k, n, _)| *k == kind && *n == name);
+                Ok(found.map(|(_, _, sha)| sha.to_string()))
+            })
+            .map(|resolved| (resolved.kind, resolved.sha))
+            .ok()
+        };
+        let resolved = |kind, sha: &str| Some((kind, sha.to_owned()));
+
+        // Branches shadow tags unless asked for explicitly
+        assert_eq!(resolve("main"), resolved(RefKind::Branch, "b1"));
+        assert_eq!(resolve("refs/tags/main"), resolved(RefKind::Tag, "t2"));
+        assert_eq!(resolve("v1"), resolved(RefKind::Tag, "t1"));
+        assert_eq!(resolve("refs/heads/v1"), None);
+        assert_eq!(resolve("abcd"), resolved(RefKind::Commit, "abcdef"));
+        // Not a sha, so never looked up as a commit
+        assert_eq!(resolve("abcdefgh"), None);
+    }
+
+    #[test]
+    fn test_github_remote() {
+        let server = StandIn::start();
+        server.route(
+            "/api/v3/repos/owner/repo/git/ref/heads/main",
+            r#"{"object":{"sha":"abc","type":"commit"}}"#,
+        );
+        server.route("/api/v3/repos/owner/repo/tarball/abc", "tarball");
+
+        let url = format!("{}/api/v3", server.url);
+        let remote = remote(RemoteKind::Github, &url, "owner/repo", "main");
+        assert_eq!(remote.host(), server.url.trim_start_matches("http://"));
+        assert_eq!(remote.resolve().unwrap().sha, "abc");
+        assert_eq!(remote.archive("abc").unwrap(), b"tarball");
+        assert!(remote.archive("def").is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests[1].target, "/api/v3/repos/owner/repo/tarball/abc");
+        assert_eq!(requests[0].headers["authorization"], "Bearer secret");
+        assert!(requests[0].headers.contains_key("x-github-api-version"));
+    }
+
+    #[test]
+    fn test_gitlab_remote() {
+        let server = StandIn::start();
+        let project = "/api/v4/projects/group%2Fsub%2Frepo/repository";
+        server.route(
+            &format!("{project}/branches/feature%2Fx"),
+            r#"{"commit":{"id":"abc"}}"#,
+        );
+        server.route(&format!("{project}/archive.tar.gz?sha=abc"), "tarball");
+
+        let remote = remote(
+            RemoteKind::Gitlab,
+            &server.url,
+            "group/sub/repo",
+            "feature/x",
+        );
+        assert_eq!(remote.resolve().unwrap().sha, "abc");
+        assert_eq!(remote.archive("abc").unwrap(), b"tarball");
+        assert_eq!(server.requests()[0].headers["private-token"], "secret");
+    }
+
+    #[test]
+    fn test_gitea_remote() {
+        let server = StandIn::start();
+        server.route(
+            "/forge/api/v1/repos/owner/repo/tags/v1.0",
+            r#"{"commit":{"sha":"abc"}}"#,
+        );
+        server.route(
+            "/forge/api/v1/repos/owner/repo/archive/abc.tar.gz",
+            "tarball",
+        );
+
+        let url = format!("{}/forge/", server.url);
+        let remote = remote(RemoteKind::Gitea, &url, "owner/repo", "v1.0");
+        let resolved = ResolvedRef {
+            sha: "abc".to_owned(),
+            kind: RefKind::Tag,
+        };
+        assert_eq!(remote.resolve().unwrap(), resolved);
+        assert_eq!(remote.archive("abc").unwrap(), b"tarball");
+        assert_eq!(
+            server.requests()[0].headers["authorization"],
+            "token secret"
+        );
+
+        let config = RemoteConfig {
+            kind: RemoteKind::Gitea,
+            base_url: None,
+            repo: "owner/repo".to_owned(),
+            reference: "main".to_owned(),
+            token: None,
+        };
+        assert!(config.remote().is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Content addressed cache of rendered cards, so that only new or modified cards need to be
+//! compiled.
+
+use std::{collections::HashMap, sync::Arc};
+
+use parking_lot::Mutex;
+use typst::{syntax::package::PackageVersion, utils::hash128};
+
+#[cfg(feature = "cache")]
+use crate::cache::RenderCacheProvider;
+use crate::{
+    cards::{CARDS_INTERNAL, CardSource, SourceConfig},
+    diagnostics::Diagnostic,
+};
+
+/// Rendered pages (svg) of a card
+#[derive(Debug, Clone)]
+pub struct CachedRender {
+    pub question: Vec<String>,
+    pub answer: Vec<String>,
+}
+
+/// A card rendered to svg
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(
+    feature = "wasm",
+    wasm_bindgen::prelude::wasm_bindgen(getter_with_clone)
+)]
+pub struct RenderedCard {
+    pub id: String,
+    pub question: Vec<String>,
+    pub answer: Vec<String>,
+    /// Errors that made the card fail to compile, in which case the pages are an error
+    /// placeholder (which isn't cached).
+    pub diagnostics: Vec<Diagnostic>,
+    /// Whether the pages come from the cache
+    pub cached: bool,
+}
+
+/// Rendered cards, along with the warnings emitted by the cards that had to be compiled
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(
+    feature = "wasm",
+    wasm_bindgen::prelude::wasm_bindgen(getter_with_clone)
+)]
+pub struct RenderOutput {
+    pub cards: Vec<RenderedCard>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Cache key of a card: everything that can change how it renders
+pub fn render_key<C: CardSource>(card: &C, config: SourceConfig, files: u128) -> String {
+    format!(
+        "{:032x}",
+        hash128(&(
+            PackageVersion::compiler().to_string(),
+            CARDS_INTERNAL,
+            config,
+            files,
+            card.deck(),
+            card.header_content(),
+            card.question(),
+            card.answer(),
+            card.cloze(),
+        ))
+    )
+}
+
+/// In memory cache of rendered cards, backed by an optional persistent provider
+#[derive(Default)]
+pub struct RenderCache {
+    memory: Mutex<HashMap<String, Arc<CachedRender>>>,
+    #[cfg(feature = "cache")]
+    provider: Mutex<Option<Box<dyn RenderCacheProvider>>>,
+}
+
+impl RenderCache {
+    /// Above that many cards, the memory cache is cleared instead of growing forever
+    const MEMORY_LIMIT: usize = 4096;
+
+    #[cfg(feature = "cache")]
+    pub fn set_provider(&self, provider: impl RenderCacheProvider) {
+        *self.provider.lock() = Some(Box::new(provider));
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<CachedRender>> {
+        if let Some(render) = self.memory.lock().get(key) {
+            return Some(render.clone());
+        }
+
+        #[cfg(feature = "cache")]
+        if let Some(provider) = self.provider.lock().as_ref()
+            && let Some(render) = provider.get_render(key)
+        {
+            let render = Arc::new(render);
+            self.remember(key, render.clone());
+            return Some(render);
+        }
+
+        None
+    }
+
+    pub fn insert(&self, key: &str, render: CachedRender) {
+        #[cfg(feature = "cache")]
+        if let Some(provider) = self.provider.lock().as_ref()
+            && let Err(err) = provider.save_render(key, &render)
+        {
+            log::warn!("Couldn't save rendered card to cache: {err}");
+        }
+
+        self.remember(key, Arc::new(render));
+    }
+
+    fn remember(&self, key: &str, render: Arc<CachedRender>) {
+        let mut memory = self.memory.lock();
+        if memory.len() >= Self::MEMORY_LIMIT {
+            memory.clear();
+        }
+        memory.insert(key.to_owned(), render);
+    }
+}
@@ -0,0 +1,101 @@
+//! Mapping of the generated `_main.typ` back to the deck files the cards were parsed from, so
+//! that errors can point at something the user actually wrote.
+
+use std::ops::Range;
+
+use crate::error::SourceSpan;
+
+/// A range of the generated source that comes from a card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    /// Id of the card the range belongs to
+    pub card_id: String,
+    /// Byte range in the generated source
+    pub start: u64,
+    pub end: u64,
+    /// Lines of the generated source the range covers, starting at 1 (end included)
+    pub first_line: u32,
+    pub last_line: u32,
+    /// Where the range comes from in the deck file
+    pub origin: SourceSpan,
+    /// Whether the range is a verbatim copy of the origin, in which case positions inside of it
+    /// can be mapped exactly. Otherwise everything maps to the start of the origin.
+    pub verbatim: bool,
+}
+
+impl SourceMapEntry {
+    fn range(&self) -> Range<usize> {
+        self.start as usize..self.end as usize
+    }
+}
+
+/// Source map of a generated source, entries are sorted and don't overlap
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    entries: Vec<SourceMapEntry>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn push(&mut self, entry: SourceMapEntry) {
+        debug_assert!(
+            self.entries
+                .last()
+                .is_none_or(|last| last.end <= entry.start)
+        );
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[SourceMapEntry] {
+        &self.entries
+    }
+
+    /// Get the entry containing a byte offset of the generated source
+    pub fn entry_at(&self, offset: usize) -> Option<&SourceMapEntry> {
+        let index = self
+            .entries
+            .partition_point(|entry| entry.end as usize <= offset);
+        self.entries
+            .get(index)
+            .filter(|entry| entry.range().contains(&offset))
+    }
+
+    /// Map a byte range of the generated source back to the deck file it comes from, returns
+    /// the id of the card it belongs to along with the span.
+    pub fn resolve(&self, generated: &str, range: Range<usize>) -> Option<(&str, SourceSpan)> {
+        let entry = self.entry_at(range.start)?;
+
+        if !entry.verbatim {
+            return Some((&entry.card_id, entry.origin.clone()));
+        }
+
+        let entry_range = entry.range();
+        let before = &generated[entry_range.start..range.start];
+        let origin = &entry.origin;
+
+        let (line, column) = match before.rfind('\n') {
+            Some(newline) => (
+                origin.line + before.matches('\n').count() as u32,
+                before[newline + 1..].chars().count() as u32 + 1,
+            ),
+            None => (origin.line, origin.column + before.chars().count() as u32),
+        };
+
+        let start = origin.start + (range.start - entry_range.start) as u64;
+        let end = origin.start + (range.end.min(entry_range.end) - entry_range.start) as u64;
+
+        Some((
+            &entry.card_id,
+            SourceSpan {
+                file: origin.file.clone(),
+                line,
+                column,
+                start,
+                end,
+            },
+        ))
+    }
+}
@@ -1,38 +1,58 @@
-#[cfg(feature = "cache")]
-use std::io::Cursor;
-
 use std::io::Read;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
     fmt::{Debug, Display},
     path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use itertools::Itertools;
 use parking_lot::Mutex;
 use typst::{
     Library, LibraryExt, World as TypstWorld,
-    diag::{FileError, FileResult},
-    foundations::Bytes,
+    diag::{FileError, FileResult, Severity as TypstSeverity, SourceDiagnostic},
+    foundations::{Bytes, Label, Selector, Value},
+    introspection::MetadataElem,
     layout::{Page, PagedDocument},
-    syntax::{FileId, Source, VirtualPath},
+    syntax::{
+        FileId, Source, Span, VirtualPath,
+        package::{PackageSpec, PackageVersion},
+    },
     text::{Font, FontBook},
-    utils::LazyHash,
+    utils::{LazyHash, PicoStr, hash128},
 };
 use typst_kit::fonts::{FontSearcher, FontSlot as TypstFontSlot};
+use walkdir::WalkDir;
 
 #[cfg(feature = "cache")]
-use crate::cache::CacheProvider;
+use crate::cache::{CacheProvider, CachedDeck, DeckKey, NoCache, RenderCacheProvider};
 use crate::{
-    cards::{CardInfo, CardSource, CardState, SourceConfig}, error::CoreError, packages::PackageProvider
+    cards::{CardInfo, CardOrigin, CardSource, CardState, SourceConfig, qualified_id},
+    codegen,
+    diagnostics::{Diagnostic, Severity, TracePoint},
+    diff::DeckDiff,
+    directives::{DirectiveWarning, Directives},
+    error::{CoreError, SourceSpan},
+    manifest::{DeckManifest, MANIFEST_FILE},
+    packages::PackageProvider,
+    render::{CachedRender, RenderCache, RenderOutput, RenderedCard, render_key},
+    source_map::SourceMap,
 };
 
+#[cfg(all(feature = "github", feature = "cache"))]
+use crate::remote;
 #[cfg(feature = "github")]
-use crate::github::GithubAPI;
+use crate::{
+    github::Github,
+    remote::{DeckRemote, RemoteConfig, ResolvedRef},
+};
 
 trait StripFirstComponent {
     fn pop_front<'a>(&'a self) -> &'a Path;
@@ -46,6 +66,45 @@ impl StripFirstComponent for Path {
     }
 }
 
+/// Package namespace the files of namespaced decks are put in, so that root relative paths
+/// (`/lib.typ`) in the files and cards of a deck resolve to the files of that deck
+const DECK_NAMESPACE: &str = "flashbang-deck";
+
+/// Id of a file of a deck, the files of the default deck (empty namespace) are at the root
+fn deck_file_id(deck: &str, path: impl AsRef<Path>) -> FileId {
+    let package = (!deck.is_empty()).then(|| PackageSpec {
+        namespace: DECK_NAMESPACE.into(),
+        name: deck.into(),
+        version: PackageVersion {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        },
+    });
+
+    FileId::new(package, VirtualPath::new(path))
+}
+
+/// Whether a file belongs to a deck, rather than to a package
+fn is_deck_file(id: FileId) -> bool {
+    id.package()
+        .is_none_or(|spec| spec.namespace == DECK_NAMESPACE)
+}
+
+/// Indices of the cards of each deck, decks are in order of first appearance
+fn deck_groups<C: CardSource>(items: &[C]) -> Vec<(String, Vec<usize>)> {
+    let mut groups = Vec::<(String, Vec<usize>)>::new();
+    for (i, item) in items.iter().enumerate() {
+        let deck = item.deck();
+        match groups.iter_mut().find(|(name, _)| *name == deck) {
+            Some((_, group)) => group.push(i),
+            None => groups.push((deck, vec![i])),
+        }
+    }
+
+    groups
+}
+
 enum FontSlot {
     Typst(TypstFontSlot),
     Extra(Font),
@@ -120,11 +179,106 @@ impl FileSlot {
     }
 }
 
+/// Limits on the files other than typst sources (images, data files...) loaded with a deck
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct AssetLimits {
+    /// Maximum size of a single asset, in bytes
+    pub max_file_size: u64,
+    /// Maximum size of all the assets of a deck, in bytes
+    pub max_total_size: u64,
+    /// Extensions of the files loaded as assets (without the dot), case insensitive
+    pub extensions: Vec<String>,
+}
+
+impl Default for AssetLimits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 8 * 1024 * 1024,
+            max_total_size: 64 * 1024 * 1024,
+            extensions: [
+                "svg", "png", "jpg", "jpeg", "gif", "webp", "pdf", "csv", "json", "toml", "yaml",
+                "yml", "xml", "txt", "bib", "cbor",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl AssetLimits {
+    fn allows(&self, path: &Path) -> bool {
+        let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+            return false;
+        };
+
+        self.extensions
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(ext))
+    }
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
 pub struct LoadError {
     error: String,
     path: String,
+    /// Where in the file the error is, if known
+    span: Option<SourceSpan>,
+}
+
+impl LoadError {
+    fn new(error: impl Into<String>, path: &Path) -> Self {
+        Self {
+            error: error.into(),
+            path: path.to_string_lossy().to_string(),
+            span: None,
+        }
+    }
+
+    fn from_core(error: CoreError, path: &Path) -> Self {
+        let span = match &error {
+            CoreError::Parsing { span, .. } => Some(span.clone()),
+            _ => None,
+        };
+
+        Self {
+            error: error.to_string(),
+            path: path.to_string_lossy().to_string(),
+            span,
+        }
+    }
+
+    /// Where in the file the error is, if known
+    pub fn span(&self) -> Option<&SourceSpan> {
+        self.span.as_ref()
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+impl LoadError {
+    #[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen(getter))]
+    pub fn error(&self) -> String {
+        self.error.clone()
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen(getter))]
+    pub fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    /// Line of the error in the file, starting at 1, if known
+    #[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen(getter))]
+    pub fn line(&self) -> Option<u32> {
+        self.span.as_ref().map(|span| span.line)
+    }
+
+    /// Column of the error in the file in characters, starting at 1, if known
+    #[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen(getter))]
+    pub fn column(&self) -> Option<u32> {
+        self.span.as_ref().map(|span| span.column)
+    }
 }
 
 impl Display for LoadError {
@@ -159,6 +313,7 @@ impl<T, E: Error> LoadErrorContext for Result<T, E> {
             Err(e) => Err(LoadError {
                 error: e.to_string(),
                 path,
+                span: None,
             }),
         }
     }
@@ -170,6 +325,30 @@ impl Error for LoadError {}
 pub struct LoadResult {
     pub cards: Vec<CardInfo>,
     pub errors: Vec<LoadError>,
+    /// Directives that were skipped (unknown, malformed) while loading the deck
+    pub warnings: Vec<DirectiveWarning>,
+    /// INCLUDE files of the deck, relative to the root of the repository
+    pub includes: Vec<String>,
+    /// Set when the deck couldn't be updated, and the cached one was loaded instead
+    pub stale: Option<StaleInfo>,
+    /// Changes since the previously loaded revision, when the deck was updated to a new one
+    pub diff: Option<DeckDiff>,
+    /// What the ref spec of the deck resolved to, when it was synced from a remote. Decks
+    /// pinned to a tag or a commit aren't expected to get updates.
+    #[cfg(feature = "github")]
+    pub reference: Option<ResolvedRef>,
+    /// The deck's `flashbang.toml`, if it has one
+    pub manifest: Option<DeckManifest>,
+}
+
+/// Why a deck was loaded from the cache instead of being updated
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct StaleInfo {
+    /// Sha of the cached deck, empty if unknown
+    pub sha: String,
+    /// Error encountered while updating
+    pub error: String,
 }
 
 pub struct WorldState {
@@ -178,6 +357,8 @@ pub struct WorldState {
     files: Mutex<HashMap<FileId, FileSlot>>,
     /// FileId of the "_main.typ" source file
     main: FileId,
+    /// Generated main sources of the prepared cards, one per deck, with their source maps
+    prepared: Mutex<Vec<(Source, SourceMap)>>,
     /// Fuzzy matching
     /// Typst world stuff
     library: LazyHash<Library>,
@@ -189,6 +370,10 @@ pub struct WorldState {
     /// Cache abstraction
     #[cfg(feature = "cache")]
     cache: Box<dyn CacheProvider>,
+    /// Rendered cards
+    renders: RenderCache,
+    /// Limits on the assets loaded with decks
+    asset_limits: Mutex<AssetLimits>,
 }
 
 impl WorldState {
@@ -224,10 +409,51 @@ impl WorldState {
             fonts,
             library: LazyHash::new(Library::default()),
             main: FileId::new(None, VirtualPath::new("_main.typ")),
+            prepared: Mutex::new(Vec::new()),
             #[cfg(feature = "cache")]
-            cache:Box::new(cache_provider),
+            cache: Box::new(cache_provider),
+            renders: RenderCache::default(),
+            asset_limits: Mutex::new(AssetLimits::default()),
         }
     }
+
+    /// World that doesn't keep decks around, whether or not the `cache` feature is enabled
+    pub fn without_cache(package_provider: impl PackageProvider) -> Self {
+        Self::new(
+            package_provider,
+            #[cfg(feature = "cache")]
+            NoCache,
+        )
+    }
+
+    /// Decks in the cache
+    #[cfg(feature = "cache")]
+    pub fn cached_decks(&self) -> Result<Vec<CachedDeck>, CoreError> {
+        self.cache.list()
+    }
+
+    /// Remove a deck from the cache
+    #[cfg(feature = "cache")]
+    pub fn evict_deck(&self, deck: &DeckKey) -> Result<(), CoreError> {
+        self.cache.evict(deck)
+    }
+
+    /// Size taken by the decks in the cache, in bytes
+    #[cfg(feature = "cache")]
+    pub fn cache_size(&self) -> Result<u64, CoreError> {
+        self.cache.size()
+    }
+
+    /// Set the limits on the assets loaded along with the next decks
+    pub fn set_asset_limits(&self, limits: AssetLimits) {
+        *self.asset_limits.lock() = limits;
+    }
+
+    /// Keep rendered cards in a persistent cache, on top of the in memory one
+    #[cfg(feature = "cache")]
+    pub fn set_render_cache_provider(&self, provider: impl RenderCacheProvider) {
+        self.renders.set_provider(provider);
+    }
 }
 
 impl WorldState {
@@ -235,179 +461,1685 @@ impl WorldState {
         &self,
         cards: &CardState,
         tarball: impl Read,
+        options: &LoadOptions,
     ) -> Result<LoadResult, CoreError> {
-        let decompressed = flate2::read::GzDecoder::new(tarball);
-        let mut archive = tar::Archive::new(decompressed);
-
-        let results = archive
-            .entries()?
-            .enumerate()
-            .map(|(id, entry)| -> Result<_, LoadError> {
-                let entry = entry.context(None)?;
-                let path = entry.path().context(None)?.to_path_buf();
+        self.load_tarball(cards, "", tarball, options)
+    }
 
-                Ok((id as u64, entry, path))
-            })
-            .filter_ok(|(_, _, path)| path.extension().and_then(|ext| ext.to_str()) == Some("typ"))
-            .map(|entry| {
-                entry.and_then(|(id, mut entry, path)| {
-                    let mut content = String::new();
-                    entry.read_to_string(&mut content).context(Some(&path))?;
-
-                    if content.starts_with("//![FLASHBANG INCLUDE]") {
-                        let file_id = FileId::new(None, VirtualPath::new(path.pop_front()));
-                        self.load_file(FileSlot::with_source(
-                            file_id,
-                            Source::new(file_id, content),
-                        ));
-
-                        Ok(Vec::new())
-                    } else {
-                        cards.parse(id, &content).context(Some(&path))
-                    }
-                })
-            });
+    fn load_tarball(
+        &self,
+        cards: &CardState,
+        deck: &str,
+        tarball: impl Read,
+        options: &LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        let mut loader = DeckLoader::new(self, cards, deck, options)?;
+        loader.read_tarball(tarball)?;
 
-        let mut load_res = LoadResult {
-            cards: Vec::new(),
-            errors: Vec::new(),
+        Ok(loader.result)
+    }
+
+    /// Parse the cards of a deck tarball without loading any of its files into the world
+    #[cfg(all(feature = "github", feature = "cache"))]
+    fn tarball_cards(
+        &self,
+        cards: &CardState,
+        deck: &str,
+        tarball: impl Read,
+        options: &LoadOptions,
+    ) -> Vec<CardInfo> {
+        let read = DeckLoader::new(self, cards, deck, options).and_then(|mut loader| {
+            loader.load_files = false;
+            loader.read_tarball(tarball).map(|_| loader.result.cards)
+        });
+
+        match read {
+            Ok(cards) => cards,
+            Err(err) => {
+                log::warn!("Couldn't read previous deck revision: {err}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Load a deck from a local directory (a checkout of a deck repository), the same way
+    /// decks are loaded from tarballs.
+    pub fn load_from_directory(
+        &self,
+        cards: &CardState,
+        path: impl AsRef<Path>,
+        options: &LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        self.load_directory(cards, "", path.as_ref(), options)
+    }
+
+    fn load_directory(
+        &self,
+        cards: &CardState,
+        deck: &str,
+        root: &Path,
+        options: &LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        let mut loader = DeckLoader::new(self, cards, deck, options)?;
+        match std::fs::read_to_string(root.join(&loader.filter.root).join(MANIFEST_FILE)) {
+            Ok(manifest) => loader.set_manifest(&manifest)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let filter = loader.filter.clone();
+        // Nothing outside of the deck root is loaded unless some of it is allowed
+        let start = if options.external.is_empty() {
+            root.join(&filter.root)
+        } else {
+            root.to_path_buf()
         };
+        let entries = WalkDir::new(start)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                relative.as_os_str().is_empty() || !filter.excludes(relative)
+            });
 
-        for res in results {
-            match res {
-                Ok(mut cards) => load_res.cards.append(&mut cards),
-                Err(error) => load_res.errors.push(error),
+        for (id, entry) in entries.enumerate() {
+            let entry = match entry {
+                Ok(entry) if entry.file_type().is_file() => entry,
+                Ok(_) => continue,
+                Err(err) => {
+                    let path = err.path().unwrap_or(root).to_path_buf();
+                    loader
+                        .result
+                        .errors
+                        .push(LoadError::new(err.to_string(), &path));
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(path);
+
+            match std::fs::File::open(path) {
+                Ok(file) => loader.load(id as u64, relative, relative, file),
+                Err(err) => loader
+                    .result
+                    .errors
+                    .push(LoadError::new(err.to_string(), relative)),
             }
         }
 
-        Ok(load_res)
+        Ok(loader.result)
     }
 
-    // Return type is like that because we can get an error and recover
-    #[cfg(all(feature = "github", feature = "cache"))]
+    /// Load a deck from a repository on github.com, `reference` being a branch, a tag or a
+    /// commit (see [`resolve_ref`](crate::remote::resolve_ref))
+    #[cfg(feature = "github")]
     pub fn load_from_github(
         &self,
         cards: &CardState,
         repo: String,
-        branch: String,
+        reference: String,
         token: Option<String>,
     ) -> Result<LoadResult, CoreError> {
-        let api = GithubAPI::new(repo, branch, token)?;
-        let latest_sha = self.cache.get_sha().unwrap_or_default();
+        let remote = Github::new(repo, reference, token)?;
+        self.load_from_remote(cards, &remote, &LoadOptions::default())
+    }
+
+    /// Load a deck from a git hosting service, keeping it in the cache. The cached deck is
+    /// loaded instead when the service can't be reached.
+    // Return type is like that because we can get an error and recover
+    #[cfg(all(feature = "github", feature = "cache"))]
+    pub fn load_from_remote(
+        &self,
+        cards: &CardState,
+        remote: &dyn DeckRemote,
+        options: &LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        self.load_remote(cards, "", remote, options)
+    }
 
-        if latest_sha == api.sha
-            && let Ok(tarball) = self.cache.get_tarball()
+    #[cfg(all(feature = "github", feature = "cache"))]
+    fn load_remote(
+        &self,
+        cards: &CardState,
+        deck: &str,
+        remote: &dyn DeckRemote,
+        options: &LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        let key = DeckKey::new(remote.host(), remote.repo(), remote.reference());
+        let reference = match remote.resolve() {
+            Ok(reference) => reference,
+            Err(err) => return self.load_stale(cards, deck, &key, options, err),
+        };
+        let sha = reference.sha.clone();
+        let latest_sha = self.cache.get_sha(&key).unwrap_or_default();
+
+        if latest_sha == sha
+            && let Ok(tarball) = self.cache.get_tarball(&key)
         {
             // Shas match and cache is working, use that
-            return Ok(self.load_from_tarball(cards, tarball)?);
+            let mut result = self.load_tarball(cards, deck, tarball, options)?;
+            result.reference = Some(reference);
+            return Ok(result);
+        }
+
+        // shas differ or cache is broken, we need to update our cache. Only the files that
+        // changed are fetched when possible.
+        let tarball = match self.patch_cached(&key, remote, &latest_sha, &sha) {
+            Some(tarball) => tarball,
+            None => match remote.archive(&sha) {
+                Ok(tarball) => tarball,
+                Err(err) => return self.load_stale(cards, deck, &key, options, err),
+            },
+        };
+
+        // Parse the previous revision before it is replaced, to tell what changed
+        let previous = self
+            .cache
+            .get_tarball(&key)
+            .ok()
+            .map(|tarball| self.tarball_cards(cards, deck, tarball, options));
+
+        self.cache.save_tarball(&key, &mut tarball.as_slice())?;
+        self.cache.save_sha(&key, sha.clone())?;
+
+        let mut result = self.load_tarball(cards, deck, tarball.as_slice(), options)?;
+        result.reference = Some(reference);
+        result.diff = previous.map(|previous| DeckDiff {
+            old_sha: latest_sha,
+            new_sha: sha,
+            ..DeckDiff::new(&previous, &result.cards)
+        });
+
+        Ok(result)
+    }
+
+    /// Bring the cached revision of a deck up to `sha` by only fetching the files that changed
+    /// since `cached_sha`. None if the remote can't tell what changed or nothing is cached.
+    #[cfg(all(feature = "github", feature = "cache"))]
+    fn patch_cached(
+        &self,
+        key: &DeckKey,
+        remote: &dyn DeckRemote,
+        cached_sha: &str,
+        sha: &str,
+    ) -> Option<Vec<u8>> {
+        if cached_sha.is_empty() {
+            return None;
         }
 
-        // shas differ or cache is broken, we need to update our cache
-        let tarball = api.get_tarball()?.bytes()?;
+        let tarball = self.cache.get_tarball(key).ok()?;
+        let changes = remote
+            .changes(cached_sha, sha)
+            .inspect_err(|err| log::warn!("Couldn't fetch deck changes: {err}"))
+            .ok()??;
+
+        remote::patch_tarball(tarball, changes)
+            .inspect_err(|err| log::warn!("Couldn't patch cached deck: {err}"))
+            .ok()
+    }
+
+    /// Load the cached deck after failing to update it, or return the error if there is no
+    /// usable cached deck.
+    #[cfg(all(feature = "github", feature = "cache"))]
+    fn load_stale(
+        &self,
+        cards: &CardState,
+        deck: &str,
+        key: &DeckKey,
+        options: &LoadOptions,
+        error: CoreError,
+    ) -> Result<LoadResult, CoreError> {
+        let Ok(tarball) = self.cache.get_tarball(key) else {
+            return Err(error);
+        };
+        let sha = self.cache.get_sha(key).unwrap_or_default();
+
+        log::warn!("Couldn't update deck, loading cached one ({sha}): {error}");
 
-        self.cache.save_tarball(&mut Cursor::new(tarball.clone()))?;
-        self.cache.save_sha(api.sha)?;
+        let mut result = self.load_tarball(cards, deck, tarball, options)?;
+        result.stale = Some(StaleInfo {
+            sha,
+            error: error.to_string(),
+        });
 
-        Ok(self.load_from_tarball(cards, Cursor::new(tarball))?)
+        Ok(result)
     }
 
+    /// Load a deck from a git hosting service
     #[cfg(all(feature = "github", not(feature = "cache")))]
-    pub fn load_from_github(
+    pub fn load_from_remote(
         &self,
         cards: &CardState,
-        repo: String,
-        branch: String,
-        token: Option<String>,
+        remote: &dyn DeckRemote,
+        options: &LoadOptions,
+    ) -> Result<LoadResult, CoreError> {
+        self.load_remote(cards, "", remote, options)
+    }
+
+    #[cfg(all(feature = "github", not(feature = "cache")))]
+    fn load_remote(
+        &self,
+        cards: &CardState,
+        deck: &str,
+        remote: &dyn DeckRemote,
+        options: &LoadOptions,
     ) -> Result<LoadResult, CoreError> {
-        let api = GithubAPI::new(repo, branch, token)?;
-        let tarball = api.get_tarball()?;
-        self.load_from_tarball(cards, tarball)
+        let reference = remote.resolve()?;
+        let tarball = remote.archive(&reference.sha)?;
+
+        let mut result = self.load_tarball(cards, deck, tarball.as_slice(), options)?;
+        result.reference = Some(reference);
+        Ok(result)
+    }
+
+    /// Load several decks side by side, each under its own namespace: the files of a deck
+    /// are only visible to that deck, and the ids of its cards are prefixed by the namespace
+    /// (`namespace:id`), except for the deck with an empty namespace.
+    ///
+    /// A deck that fails to load doesn't prevent the others from loading, the failure is
+    /// reported in its errors.
+    pub fn load_decks(
+        &self,
+        cards: &CardState,
+        decks: Vec<NamespacedDeck>,
+    ) -> Result<MergedLoadResult, CoreError> {
+        for (i, deck) in decks.iter().enumerate() {
+            let namespace = &deck.namespace;
+            if !namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(CoreError::Other {
+                    details: format!(
+                        "invalid deck namespace \"{namespace}\", only ascii letters, digits, \
+                         - and _ are allowed"
+                    ),
+                });
+            }
+            if decks[..i].iter().any(|other| other.namespace == *namespace) {
+                return Err(CoreError::Other {
+                    details: format!("deck namespace \"{namespace}\" is used more than once"),
+                });
+            }
+        }
+
+        let mut merged = MergedLoadResult {
+            decks: Vec::new(),
+            collisions: Vec::new(),
+        };
+        let mut ids = BTreeMap::<String, Vec<String>>::new();
+
+        for NamespacedDeck {
+            namespace,
+            source,
+            options,
+        } in decks
+        {
+            let deck = namespace.as_str();
+            let result = match source {
+                DeckSource::Tarball { data } => self.load_tarball(cards, deck, &data[..], &options),
+                DeckSource::Directory { path } => {
+                    self.load_directory(cards, deck, Path::new(&path), &options)
+                }
+                #[cfg(feature = "github")]
+                DeckSource::Github {
+                    repo,
+                    reference,
+                    token,
+                } => Github::new(repo, reference, token)
+                    .and_then(|remote| self.load_remote(cards, deck, &remote, &options)),
+                #[cfg(feature = "github")]
+                DeckSource::Remote { config } => config
+                    .remote()
+                    .and_then(|remote| self.load_remote(cards, deck, remote.as_ref(), &options)),
+            };
+
+            let result = result.unwrap_or_else(|err| LoadResult {
+                cards: Vec::new(),
+                errors: vec![LoadError::new(err.to_string(), Path::new(""))],
+                warnings: Vec::new(),
+                includes: Vec::new(),
+                stale: None,
+                diff: None,
+                #[cfg(feature = "github")]
+                reference: None,
+                manifest: None,
+            });
+
+            let prefix = qualified_id(&namespace, "");
+            for card in &result.cards {
+                let id = card.id.strip_prefix(&prefix).unwrap_or(&card.id);
+                let decks = ids.entry(id.to_owned()).or_default();
+                if !decks.contains(&namespace) {
+                    decks.push(namespace.clone());
+                }
+            }
+
+            merged.decks.push(DeckLoadResult { namespace, result });
+        }
+
+        merged.collisions = ids
+            .into_iter()
+            .filter(|(_, decks)| decks.len() > 1)
+            .map(|(id, decks)| IdCollision { id, decks })
+            .collect();
+
+        Ok(merged)
     }
 
+    /// Prepare the source of a set of cards to be compiled with [`Self::compile`]. Cards of
+    /// different decks get their own source, so that their imports resolve against their
+    /// deck, and are compiled one deck after the other (in order of first appearance).
     pub fn prepare_source<C: CardSource>(
         &self,
         cards: &CardState,
         items: impl IntoIterator<Item = C>,
         config: SourceConfig,
     ) -> Result<(), CoreError> {
-        let content = cards.build_source(items, config)?;
+        let items = items.into_iter().collect_vec();
+        let mut groups = deck_groups(&items);
+        if groups.is_empty() {
+            // No cards still makes a (empty) document
+            groups.push((String::new(), Vec::new()));
+        }
 
-        self.files.lock().insert(
-            self.main,
-            FileSlot::with_source(self.main, Source::new(self.main, content)),
-        );
+        let prepared = groups
+            .into_iter()
+            .map(|(deck, group)| {
+                let (content, source_map) =
+                    cards.build_source(group.iter().map(|&i| &items[i]), config)?;
+                Ok((
+                    Source::new(deck_file_id(&deck, "_main.typ"), content),
+                    source_map,
+                ))
+            })
+            .collect::<Result<Vec<_>, CoreError>>()?;
+
+        *self.prepared.lock() = prepared;
 
         Ok(())
     }
-    pub fn compile(&self) -> Result<Vec<CardPage>, CoreError> {
-        let output = typst::compile::<PagedDocument>(&self)
-            .output
-            .map_err(|errors| CoreError::Typst {
-                details: format!("{errors:?}"),
-            })?;
+    pub fn compile(&self) -> Result<CompileOutput, CoreError> {
+        let prepared = self.prepared.lock().clone();
+        if prepared.is_empty() {
+            return Err(CoreError::Other {
+                details: "no source has been prepared".to_owned(),
+            });
+        }
 
-        Ok(output
-            .pages
-            .into_iter()
-            .map(|p| CardPage(p))
-            .collect_vec())
-    }
-    pub fn inspect_source(&self) -> Option<String> {
-        Some(self.get_file(&self.main)?.source().ok()?.text().to_string())
-    }
-}
+        let mut output = CompileOutput {
+            cards: Vec::new(),
+            warnings: Vec::new(),
+        };
+        for (main, source_map) in prepared {
+            let compiled = Compilation {
+                world: self,
+                main,
+                source_map,
+            }
+            .compile()?;
 
-impl WorldState {
-    /// Add a file to the loaded files map
-    pub fn load_file(&self, slot: FileSlot) -> Option<FileSlot> {
-        self.files.lock().insert(slot.id, slot)
-    }
+            output.cards.extend(compiled.cards);
+            output.warnings.extend(compiled.warnings);
+        }
 
-    /// Get a file
-    pub fn get_file(&self, id: &FileId) -> Option<FileSlot> {
-        self.files.lock().get(id).cloned()
+        Ok(output)
     }
-}
+    /// Compile a set of cards, isolating the ones that fail to compile: they get an error
+    /// placeholder along with their diagnostics, and every other card is still rendered.
+    ///
+    /// Failing groups are split, first by isolating the cards the errors point to, and by
+    /// bisection when that doesn't help. This doesn't touch the prepared source.
+    pub fn compile_isolated<C: CardSource>(
+        &self,
+        cards: &CardState,
+        items: &[C],
+        config: SourceConfig,
+    ) -> Result<CompileOutput, CoreError> {
+        let mut results = vec![None; items.len()];
+        let mut warnings = Vec::new();
+        // Cards of different decks never share a compilation, see Self::prepare_source
+        let mut pending = deck_groups(items)
+            .into_iter()
+            .rev()
+            .map(|(_, group)| group)
+            .collect_vec();
 
-/// Newtype around typst::layout::Page because I need it to derive uniffi::Object
-#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
-#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
-pub struct CardPage(Page);
+        while let Some(group) = pending.pop() {
+            let deck = items[group[0]].deck();
+            let group_items = group.iter().map(|&i| &items[i]);
+            let compiled = Compilation::new(self, cards, &deck, group_items, config)
+                .and_then(|compilation| compilation.compile());
 
-#[cfg_attr(feature = "uniffi", uniffi::export)]
-#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
-impl CardPage {
-    pub fn svg(&self) -> String {
-        typst_svg::svg(&self.0)
-    }
-}
+            let diagnostics = match compiled {
+                Ok(output) => {
+                    warnings.extend(output.warnings);
+                    for (&i, card) in group.iter().zip(output.cards) {
+                        results[i] = Some(card);
+                    }
+                    continue;
+                }
+                Err(CoreError::Typst { diagnostics }) => diagnostics,
+                Err(CoreError::InvalidCard { id, reason }) => vec![Diagnostic {
+                    severity: Severity::Error,
+                    message: reason,
+                    hints: Vec::new(),
+                    trace: Vec::new(),
+                    file_id: None,
+                    start: 0,
+                    end: 0,
+                    location: None,
+                    card_id: Some(id),
+                }],
+                Err(err) => return Err(err),
+            };
 
-impl TypstWorld for WorldState {
-    fn library(&self) -> &LazyHash<Library> {
-        &self.library
-    }
-    fn book(&self) -> &LazyHash<FontBook> {
-        &self.book
-    }
-    fn main(&self) -> FileId {
-        self.main
-    }
-    fn source(&self, id: FileId) -> FileResult<Source> {
-        if id.package().is_some() {
-            self.packages.get_package_source(id, &self)
-        } else if let Some(slot) = self.get_file(&id) {
-            slot.source()
-        } else {
-            Err(FileError::AccessDenied)
+            if let [i] = group[..] {
+                results[i] = Some(self.placeholder(cards, &items[i], diagnostics, config)?);
+                continue;
+            }
+
+            let (blamed, rest): (Vec<_>, Vec<_>) = group.iter().partition(|&&i| {
+                let id = items[i].id();
+                diagnostics
+                    .iter()
+                    .any(|diagnostic| diagnostic.card_id.as_ref() == Some(&id))
+            });
+
+            if !blamed.is_empty() && !rest.is_empty() {
+                pending.push(rest);
+                pending.extend(blamed.into_iter().map(|i| vec![i]));
+            } else {
+                let (first, second) = group.split_at(group.len() / 2);
+                pending.push(second.to_vec());
+                pending.push(first.to_vec());
+            }
         }
+
+        Ok(CompileOutput {
+            cards: results.into_iter().flatten().collect(),
+            warnings,
+        })
     }
-    fn file(&self, id: FileId) -> FileResult<Bytes> {
-        if id.package().is_some() {
-            self.packages.get_package_file(id, &self)
-        } else if let Some(slot) = self.get_file(&id) {
-            slot.bytes()
+
+    /// Compile cards in chunks spread over multiple threads, with the same isolation of
+    /// failures as [`Self::compile_isolated`]. The cards are returned in order.
+    pub fn compile_parallel<C: CardSource>(
+        &self,
+        cards: &CardState,
+        items: &[C],
+        config: SourceConfig,
+        options: CompileOptions,
+    ) -> Result<CompileOutput, CoreError> {
+        let chunks = items
+            .chunks(options.chunk_size.max(1) as usize)
+            .collect_vec();
+        let threads = (options.threads as usize).clamp(1, chunks.len().max(1));
+
+        let results = if threads == 1 {
+            chunks
+                .iter()
+                .map(|chunk| self.compile_isolated(cards, chunk, config))
+                .collect_vec()
         } else {
-            Err(FileError::AccessDenied)
+            let next = AtomicUsize::new(0);
+            let results = Mutex::new((0..chunks.len()).map(|_| None).collect_vec());
+
+            std::thread::scope(|scope| {
+                for _ in 0..threads {
+                    scope.spawn(|| {
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(chunk) = chunks.get(index) else {
+                                break;
+                            };
+                            let output = self.compile_isolated(cards, chunk, config);
+                            results.lock()[index] = Some(output);
+                        }
+                    });
+                }
+            });
+
+            results.into_inner().into_iter().flatten().collect_vec()
+        };
+
+        let mut output = CompileOutput {
+            cards: Vec::with_capacity(items.len()),
+            warnings: Vec::new(),
+        };
+        for result in results {
+            let result = result?;
+            output.cards.extend(result.cards);
+            output.warnings.extend(result.warnings);
+        }
+
+        Ok(output)
+    }
+
+    /// Render cards to svg, only compiling the ones that aren't in the render cache. Cards
+    /// that fail to compile are isolated like in [`Self::compile_isolated`].
+    pub fn render<C: CardSource>(
+        &self,
+        cards: &CardState,
+        items: &[C],
+        config: SourceConfig,
+        options: CompileOptions,
+    ) -> Result<RenderOutput, CoreError> {
+        let mut fingerprints = HashMap::new();
+        let keys = items
+            .iter()
+            .map(|item| {
+                let deck = item.deck();
+                let files = *fingerprints
+                    .entry(deck)
+                    .or_insert_with_key(|deck| self.files_fingerprint(deck));
+                render_key(item, config, files)
+            })
+            .collect_vec();
+
+        let mut rendered = items
+            .iter()
+            .zip(&keys)
+            .map(|(item, key)| {
+                let cached = self.renders.get(key)?;
+                Some(RenderedCard {
+                    id: item.id(),
+                    question: cached.question.clone(),
+                    answer: cached.answer.clone(),
+                    diagnostics: Vec::new(),
+                    cached: true,
+                })
+            })
+            .collect_vec();
+
+        let missing = (0..items.len())
+            .filter(|&i| rendered[i].is_none())
+            .collect_vec();
+        let mut warnings = Vec::new();
+
+        if !missing.is_empty() {
+            let output = self.compile_parallel(
+                cards,
+                &missing.iter().map(|&i| &items[i]).collect_vec(),
+                config,
+                options,
+            )?;
+            warnings = output.warnings;
+
+            for (&i, card) in missing.iter().zip(output.cards) {
+                let svg = |pages: &[Arc<CardPage>]| pages.iter().map(|p| p.svg()).collect_vec();
+                let render = CachedRender {
+                    question: svg(&card.question),
+                    answer: svg(&card.answer),
+                };
+
+                // Placeholders aren't cached so that the card gets another chance next time
+                if card.diagnostics.is_empty() {
+                    self.renders.insert(&keys[i], render.clone());
+                }
+
+                rendered[i] = Some(RenderedCard {
+                    id: card.id,
+                    question: render.question,
+                    answer: render.answer,
+                    diagnostics: card.diagnostics,
+                    cached: false,
+                });
+            }
+        }
+
+        Ok(RenderOutput {
+            cards: rendered.into_iter().flatten().collect(),
+            warnings,
+        })
+    }
+
+    /// Hash of the files of a deck (excluding packages, which don't change), since its cards
+    /// can depend on any of them. The files of other decks are out of their reach.
+    fn files_fingerprint(&self, deck: &str) -> u128 {
+        let files = self.files.lock();
+        let mut local = files
+            .values()
+            .filter(|slot| {
+                is_deck_file(slot.id)
+                    && slot.id.package().map_or("", |spec| spec.name.as_str()) == deck
+            })
+            .map(|slot| {
+                let path = slot.id.vpath().as_rooted_path().to_path_buf();
+                let source = slot.source().ok().map(|source| hash128(source.text()));
+                let bytes = slot.bytes().ok().map(|bytes| hash128(bytes.as_slice()));
+                (path, source, bytes)
+            })
+            .collect_vec();
+
+        local.sort();
+        hash128(&local)
+    }
+
+    /// Compile the error placeholder of a card that failed to compile
+    fn placeholder<C: CardSource>(
+        &self,
+        cards: &CardState,
+        card: &C,
+        diagnostics: Vec<Diagnostic>,
+        config: SourceConfig,
+    ) -> Result<CompiledCard, CoreError> {
+        let message = diagnostics
+            .first()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .unwrap_or_default();
+
+        let placeholder = Placeholder::new(card, message);
+        let compilation = Compilation::new(self, cards, &card.deck(), [placeholder], config)?;
+        let compiled = compilation.compile()?.cards.into_iter().next();
+        let compiled = compiled.ok_or_else(|| CoreError::Other {
+            details: "error placeholder didn't produce any card".to_owned(),
+        })?;
+
+        Ok(CompiledCard {
+            id: card.id(),
+            diagnostics,
+            ..compiled
+        })
+    }
+    pub fn inspect_source(&self) -> Option<String> {
+        let prepared = self.prepared.lock();
+        if prepared.is_empty() {
+            return None;
+        }
+
+        Some(prepared.iter().map(|(main, _)| main.text()).join("\n"))
+    }
+    /// Find where a span of the prepared source is, spans in the generated main sources are
+    /// mapped back to the deck files through the source maps when possible.
+    pub fn locate(&self, span: Span) -> Option<SourceSpan> {
+        let prepared = self.prepared.lock();
+        let (main, source_map) = prepared
+            .iter()
+            .find(|(main, _)| span.id() == Some(main.id()))
+            .or(prepared.first())?;
+
+        Compilation {
+            world: self,
+            main: main.clone(),
+            source_map: source_map.clone(),
+        }
+        .locate(span)
+    }
+}
+
+/// A compilation of a generated main source. Everything else (fonts, library, files, packages)
+/// is shared with the world state, so that several compilations can run at once.
+struct Compilation<'a> {
+    world: &'a WorldState,
+    main: Source,
+    source_map: SourceMap,
+}
+
+impl<'a> Compilation<'a> {
+    /// Compilation of cards from the given deck
+    fn new<C: CardSource>(
+        world: &'a WorldState,
+        cards: &CardState,
+        deck: &str,
+        items: impl IntoIterator<Item = C>,
+        config: SourceConfig,
+    ) -> Result<Self, CoreError> {
+        let (content, source_map) = cards.build_source(items, config)?;
+
+        Ok(Self {
+            world,
+            main: Source::new(deck_file_id(deck, "_main.typ"), content),
+            source_map,
+        })
+    }
+
+    /// Deck files are only visible to the cards of their deck, which the main source is in
+    fn check_deck(&self, id: FileId) -> FileResult<()> {
+        if is_deck_file(id) && id.package() != self.main.id().package() {
+            return Err(FileError::NotFound(
+                id.vpath().as_rooted_path().to_path_buf(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn compile(&self) -> Result<CompileOutput, CoreError> {
+        let result = typst::compile::<PagedDocument>(self);
+        let warnings = self.diagnostics(&result.warnings);
+        let document = result.output.map_err(|errors| CoreError::Typst {
+            diagnostics: self.diagnostics(&errors),
+        })?;
+
+        Ok(CompileOutput {
+            cards: split_cards(document)?,
+            warnings,
+        })
+    }
+
+    /// Find where a span is, spans in the generated main source are mapped back to the deck
+    /// files through the source map when possible.
+    fn locate(&self, span: Span) -> Option<SourceSpan> {
+        self.resolve_span(span).map(|resolved| resolved.location)
+    }
+
+    fn resolve_span(&self, span: Span) -> Option<ResolvedSpan> {
+        let id = span.id()?;
+        let source = self.source(id).ok()?;
+        let range = source.range(span)?;
+
+        let file_id = match id.package() {
+            Some(spec) => format!("{spec}{}", id.vpath().as_rooted_path().display()),
+            None => id.vpath().as_rooted_path().display().to_string(),
+        };
+
+        if id == self.main.id()
+            && let Some((card_id, location)) = self.source_map.resolve(source.text(), range.clone())
+        {
+            return Some(ResolvedSpan {
+                file_id,
+                range,
+                location,
+                card_id: Some(card_id.to_owned()),
+            });
+        }
+
+        let path = id.vpath().as_rootless_path().to_string_lossy();
+        Some(ResolvedSpan {
+            location: SourceSpan::new(&path, source.text(), range.clone()),
+            file_id,
+            range,
+            card_id: None,
+        })
+    }
+
+    /// Convert typst diagnostics, resolving their spans
+    fn diagnostics(&self, diagnostics: &[SourceDiagnostic]) -> Vec<Diagnostic> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let resolved = self.resolve_span(diagnostic.span);
+                let (file_id, range, location, card_id) = match resolved {
+                    Some(r) => (Some(r.file_id), r.range, Some(r.location), r.card_id),
+                    None => (None, 0..0, None, None),
+                };
+
+                Diagnostic {
+                    severity: match diagnostic.severity {
+                        TypstSeverity::Error => Severity::Error,
+                        TypstSeverity::Warning => Severity::Warning,
+                    },
+                    message: diagnostic.message.to_string(),
+                    hints: diagnostic.hints.iter().map(ToString::to_string).collect(),
+                    trace: diagnostic
+                        .trace
+                        .iter()
+                        .map(|point| TracePoint {
+                            message: point.v.to_string(),
+                            location: self.locate(point.span),
+                        })
+                        .collect(),
+                    file_id,
+                    start: range.start as u64,
+                    end: range.end as u64,
+                    location,
+                    card_id,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Which files of a deck are loaded, whatever it is loaded from
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct LoadOptions {
+    /// Globs of the paths (relative to the root of the repository) to skip, excluding a
+    /// directory excludes everything in it
+    pub exclude: Vec<String>,
+    /// Directory of the deck in the repository (`decks/linear-algebra`), cards are only looked
+    /// for in it. Empty for the whole repository.
+    pub root: String,
+    /// Globs of the paths outside of the deck root that INCLUDE files and assets are still
+    /// loaded from (`shared/**`), allowing a directory allows everything in it
+    pub external: Vec<String>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            exclude: vec!["**/.git".to_owned()],
+            root: String::new(),
+            external: Vec::new(),
+        }
+    }
+}
+
+/// What is loaded from a file of a deck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// Cards, INCLUDE files and assets
+    Deck,
+    /// INCLUDE files and assets only
+    External,
+}
+
+/// [`LoadOptions`] and the manifest of the deck, ready to match paths against
+#[derive(Debug, Clone)]
+struct DeckFilter {
+    root: PathBuf,
+    excluded: GlobSet,
+    external: GlobSet,
+    /// Files of the deck cards are looked for in, None for all of them
+    cards: Option<GlobSet>,
+    /// Where the assets of the deck are loaded from, None for anywhere
+    assets: Option<GlobSet>,
+}
+
+impl DeckFilter {
+    fn new(options: &LoadOptions, manifest: Option<&DeckManifest>) -> Result<Self, CoreError> {
+        let root = PathBuf::from(options.root.trim_matches('/'));
+        // The globs of the manifest are relative to the deck root
+        let in_root = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    let pattern = root.join(pattern.trim_start_matches('/'));
+                    pattern.to_string_lossy().into_owned()
+                })
+                .collect_vec()
+        };
+
+        let mut excluded = options.exclude.clone();
+        let mut cards = None;
+        let mut assets = None;
+        if let Some(manifest) = manifest {
+            excluded.extend(in_root(&manifest.exclude));
+            if !manifest.cards.is_empty() {
+                cards = Some(Self::globs(&in_root(&manifest.cards), "cards")?);
+            }
+            if !manifest.assets.is_empty() {
+                assets = Some(Self::globs(&in_root(&manifest.assets), "assets")?);
+            }
+        }
+
+        Ok(Self {
+            excluded: Self::globs(&excluded, "exclusion")?,
+            external: Self::globs(&options.external, "external path")?,
+            root,
+            cards,
+            assets,
+        })
+    }
+
+    fn globs(patterns: &[String], what: &str) -> Result<GlobSet, CoreError> {
+        let mut set = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern).map_err(|err| CoreError::Other {
+                details: format!("invalid {what} pattern \"{pattern}\": {err}"),
+            })?;
+            set.add(glob);
+        }
+        set.build().map_err(|err| CoreError::Other {
+            details: format!("invalid {what} patterns: {err}"),
+        })
+    }
+
+    /// Whether a path or one of the directories it is in matches
+    fn matches(set: &GlobSet, path: &Path) -> bool {
+        path.ancestors()
+            .take_while(|path| !path.as_os_str().is_empty())
+            .any(|path| set.is_match(path))
+    }
+
+    /// Whether a path (relative to the root of the repository) is excluded
+    fn excludes(&self, path: &Path) -> bool {
+        Self::matches(&self.excluded, path)
+    }
+
+    /// What is loaded from a file, None if it is skipped
+    fn scope(&self, path: &Path) -> Option<Scope> {
+        if self.excludes(path) {
+            None
+        } else if path.starts_with(&self.root) {
+            Some(Scope::Deck)
+        } else if Self::matches(&self.external, path) {
+            Some(Scope::External)
+        } else {
+            None
+        }
+    }
+
+    /// Whether cards are looked for in a file of the deck
+    fn has_cards(&self, path: &Path) -> bool {
+        self.cards
+            .as_ref()
+            .is_none_or(|set| Self::matches(set, path))
+    }
+
+    /// Whether an asset of the deck is loaded
+    fn has_asset(&self, path: &Path) -> bool {
+        self.assets
+            .as_ref()
+            .is_none_or(|set| Self::matches(set, path))
+    }
+}
+
+/// Where a deck is loaded from
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum DeckSource {
+    /// Gzipped tarball, with everything in a top level directory (like the ones from github)
+    Tarball { data: Vec<u8> },
+    /// Local directory, see [`WorldState::load_from_directory`]
+    Directory { path: String },
+    /// Github repository, see [`WorldState::load_from_github`]
+    #[cfg(feature = "github")]
+    Github {
+        repo: String,
+        /// Branch, tag or commit
+        reference: String,
+        token: Option<String>,
+    },
+    /// Repository on any supported git hosting service, see [`WorldState::load_from_remote`]
+    #[cfg(feature = "github")]
+    Remote { config: RemoteConfig },
+}
+
+/// A deck loaded alongside others, see [`WorldState::load_decks`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct NamespacedDeck {
+    /// Namespace of the deck, made of ascii letters, digits, `-` and `_`. At most one deck
+    /// can have the empty namespace, the ids of its cards aren't prefixed.
+    pub namespace: String,
+    pub source: DeckSource,
+    pub options: LoadOptions,
+}
+
+/// Result of [`WorldState::load_decks`]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct MergedLoadResult {
+    /// Result of each deck, in the order they were given
+    pub decks: Vec<DeckLoadResult>,
+    /// Card ids used by several decks. The cards are kept apart by their namespace, but this
+    /// usually means that a card was copied from one deck to another.
+    pub collisions: Vec<IdCollision>,
+}
+
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct DeckLoadResult {
+    pub namespace: String,
+    pub result: LoadResult,
+}
+
+/// A card id (without namespace) used by several decks
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct IdCollision {
+    pub id: String,
+    /// Namespaces of the decks using the id
+    pub decks: Vec<String>,
+}
+
+/// Loads the files of a deck into a world state, wherever they come from
+struct DeckLoader<'a> {
+    world: &'a WorldState,
+    cards: &'a CardState,
+    /// Namespace of the deck
+    deck: &'a str,
+    options: LoadOptions,
+    filter: DeckFilter,
+    limits: AssetLimits,
+    assets_size: u64,
+    result: LoadResult,
+    /// Whether files are loaded into the world, or only parsed for cards
+    load_files: bool,
+}
+
+impl<'a> DeckLoader<'a> {
+    fn new(
+        world: &'a WorldState,
+        cards: &'a CardState,
+        deck: &'a str,
+        options: &LoadOptions,
+    ) -> Result<Self, CoreError> {
+        Ok(Self {
+            world,
+            cards,
+            deck,
+            options: options.clone(),
+            filter: DeckFilter::new(options, None)?,
+            limits: world.asset_limits.lock().clone(),
+            assets_size: 0,
+            result: LoadResult {
+                cards: Vec::new(),
+                errors: Vec::new(),
+                warnings: Vec::new(),
+                includes: Vec::new(),
+                stale: None,
+                diff: None,
+                #[cfg(feature = "github")]
+                reference: None,
+                manifest: None,
+            },
+            load_files: true,
+        })
+    }
+
+    /// Apply the manifest of the deck, before any of its files are loaded. The packages it
+    /// lists are fetched right away, rather than when the cards are first compiled.
+    fn set_manifest(&mut self, content: &str) -> Result<(), CoreError> {
+        let manifest = DeckManifest::parse(content)?;
+        self.filter = DeckFilter::new(&self.options, Some(&manifest))?;
+
+        if self.load_files {
+            let path = self.filter.root.join(MANIFEST_FILE);
+            let specs = manifest
+                .package_specs()
+                .map_err(|details| CoreError::Other { details })?;
+            for spec in specs {
+                let id = FileId::new(Some(spec.clone()), VirtualPath::new("typst.toml"));
+                if let Err(err) = self.world.packages.get_package_file(id, self.world) {
+                    let error = format!("couldn't fetch package {spec}: {err}");
+                    self.result.errors.push(LoadError::new(error, &path));
+                }
+            }
+        }
+
+        self.result.manifest = Some(manifest);
+        Ok(())
+    }
+
+    /// Load the files of a deck tarball in a single pass. The manifest can be anywhere in it,
+    /// so the files met before it are kept aside until it is found.
+    fn read_tarball(&mut self, tarball: impl Read) -> Result<(), CoreError> {
+        let manifest_path = self.filter.root.join(MANIFEST_FILE);
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+        // None once the manifest is found
+        let mut pending = Some(Vec::<PendingFile>::new());
+
+        for (id, entry) in archive.entries()?.enumerate() {
+            let id = id as u64;
+            let entry = entry.context(None).and_then(|entry| {
+                let path = entry.path().context(None)?.to_path_buf();
+                Ok((entry, path))
+            });
+
+            let (mut entry, path) = match entry {
+                Ok((entry, path)) if entry.header().entry_type().is_file() => (entry, path),
+                Ok(_) => continue,
+                Err(error) => {
+                    self.result.errors.push(error);
+                    continue;
+                }
+            };
+            // Tarballs from github have everything in a top level directory
+            let relative = path.pop_front().to_path_buf();
+
+            if pending.is_some() && relative == manifest_path {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                self.set_manifest(&content)?;
+                self.load(id, &path, &relative, content.as_bytes());
+
+                for file in pending.take().into_iter().flatten() {
+                    self.load(file.id, &file.path, &file.relative, file.content.as_slice());
+                }
+            } else if let Some(files) = &mut pending {
+                match self.defer(id, &path, &relative, entry) {
+                    Ok(Some(file)) => files.push(file),
+                    Ok(None) => {}
+                    Err(error) => self.result.errors.push(error),
+                }
+            } else {
+                self.load(id, &path, &relative, entry);
+            }
+        }
+
+        // No manifest, the files kept aside are loaded as they are
+        for file in pending.into_iter().flatten() {
+            self.load(file.id, &file.path, &file.relative, file.content.as_slice());
+        }
+
+        Ok(())
+    }
+
+    /// Read a file of a tarball met before the manifest, unless it is skipped whatever the
+    /// manifest says (a manifest can only narrow down what is loaded)
+    fn defer(
+        &self,
+        id: u64,
+        path: &Path,
+        relative: &Path,
+        mut file: impl Read,
+    ) -> Result<Option<PendingFile>, LoadError> {
+        if self.filter.scope(relative).is_none() {
+            return Ok(None);
+        }
+
+        let mut content = Vec::new();
+        if relative.extension().and_then(|ext| ext.to_str()) == Some("typ") {
+            file.read_to_end(&mut content)
+        } else if self.load_files && self.limits.allows(relative) {
+            // One byte past the limit is enough for load_asset to know the file is too big
+            file.take(self.limits.max_file_size + 1)
+                .read_to_end(&mut content)
+        } else {
+            return Ok(None);
+        }
+        .context(Some(&path.to_path_buf()))?;
+
+        Ok(Some(PendingFile {
+            id,
+            path: path.to_path_buf(),
+            relative: relative.to_path_buf(),
+            content,
+        }))
+    }
+
+    /// Load a file: typst sources are parsed for cards (or kept as is for INCLUDE files),
+    /// anything else is an asset. `path` is used in errors, `relative` is the path of the
+    /// file in the deck.
+    fn load(&mut self, id: u64, path: &Path, relative: &Path, file: impl Read) {
+        let Some(scope) = self.filter.scope(relative) else {
+            return;
+        };
+
+        let result = if relative.extension().and_then(|ext| ext.to_str()) == Some("typ") {
+            self.load_source(id, path, relative, scope, file)
+        } else if scope == Scope::External || self.filter.has_asset(relative) {
+            self.load_asset(path, relative, file).map(|_| Vec::new())
+        } else {
+            Ok(Vec::new())
+        };
+
+        match result {
+            Ok(mut cards) => self.result.cards.append(&mut cards),
+            Err(error) => self.result.errors.push(error),
+        }
+    }
+
+    fn load_source(
+        &mut self,
+        id: u64,
+        path: &Path,
+        relative: &Path,
+        scope: Scope,
+        mut file: impl Read,
+    ) -> Result<Vec<CardInfo>, LoadError> {
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .context(Some(&path.to_path_buf()))?;
+
+        let file = relative.to_string_lossy();
+        let (directives, warnings) = Directives::parse(&file, &content);
+        self.result.warnings.extend(warnings);
+
+        if directives.ignore {
+            return Ok(Vec::new());
+        }
+
+        if directives.include {
+            if !self.load_files {
+                return Ok(Vec::new());
+            }
+
+            let file_id = deck_file_id(self.deck, relative);
+            self.world.load_file(FileSlot::with_source(
+                file_id,
+                Source::new(file_id, content),
+            ));
+            self.result.includes.push(file.into_owned());
+
+            return Ok(Vec::new());
+        }
+
+        // Cards are only looked for in the deck root, in the files the manifest allows
+        if scope == Scope::External || !self.filter.has_cards(relative) {
+            return Ok(Vec::new());
+        }
+
+        let mut cards = self
+            .cards
+            .parse_with(id, &file, &content, &directives)
+            .map_err(|err| LoadError::from_core(err, path))?;
+
+        for card in &mut cards {
+            card.id = qualified_id(self.deck, &card.id);
+            card.deck = self.deck.to_owned();
+        }
+
+        Ok(cards)
+    }
+
+    /// Load a file other than a typst source as bytes, if the limits allow it
+    fn load_asset(
+        &mut self,
+        path: &Path,
+        relative: &Path,
+        file: impl Read,
+    ) -> Result<(), LoadError> {
+        if !self.load_files {
+            return Ok(());
+        }
+        if !self.limits.allows(relative) {
+            log::debug!("Skipping {path:?}, not an allowed asset");
+            return Ok(());
+        }
+
+        // Read one byte past the limit to know whether the file is too big
+        let mut bytes = Vec::new();
+        file.take(self.limits.max_file_size + 1)
+            .read_to_end(&mut bytes)
+            .context(Some(&path.to_path_buf()))?;
+
+        let size = bytes.len() as u64;
+        if size > self.limits.max_file_size {
+            return Err(LoadError::new(
+                format!("asset is larger than {} bytes", self.limits.max_file_size),
+                path,
+            ));
+        }
+        if self.assets_size + size > self.limits.max_total_size {
+            return Err(LoadError::new(
+                format!(
+                    "assets of the deck are larger than {} bytes",
+                    self.limits.max_total_size
+                ),
+                path,
+            ));
+        }
+        self.assets_size += size;
+
+        let file_id = deck_file_id(self.deck, relative);
+        self.world
+            .load_file(FileSlot::with_bytes(file_id, Bytes::new(bytes)));
+
+        Ok(())
+    }
+}
+
+/// A file of a deck tarball met before its manifest, see [`DeckLoader::read_tarball`]
+struct PendingFile {
+    id: u64,
+    path: PathBuf,
+    relative: PathBuf,
+    content: Vec<u8>,
+}
+
+/// A span resolved to its file and to the deck file it comes from
+struct ResolvedSpan {
+    file_id: String,
+    range: std::ops::Range<usize>,
+    location: SourceSpan,
+    card_id: Option<String>,
+}
+
+/// How to split a compilation across threads
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+pub struct CompileOptions {
+    /// Number of cards compiled together, smaller chunks spread better over threads but
+    /// share less work
+    pub chunk_size: u32,
+    /// Number of threads compiling at once, 1 to compile on the calling thread
+    pub threads: u32,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 32,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get() as u32),
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+impl CompileOptions {
+    #[wasm_bindgen::prelude::wasm_bindgen(constructor)]
+    pub fn new(chunk_size: u32, threads: u32) -> Self {
+        Self {
+            chunk_size,
+            threads,
+        }
+    }
+}
+
+/// Cards of a successful compilation, along with the warnings emitted
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CompileOutput {
+    pub cards: Vec<CompiledCard>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// The pages of a card
+#[derive(Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CompiledCard {
+    pub id: String,
+    pub question: Vec<Arc<CardPage>>,
+    pub answer: Vec<Arc<CardPage>>,
+    /// Errors that made the card fail to compile, in which case the pages are an error
+    /// placeholder. Always empty when compiling without isolating failures.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Stand-in for a card that failed to compile, showing an error instead of its content
+struct Placeholder {
+    name: String,
+    deck: String,
+    content: String,
+}
+
+impl Placeholder {
+    fn new<C: CardSource>(card: &C, message: &str) -> Self {
+        Self {
+            name: card.name(),
+            deck: card.deck(),
+            content: format!("\n#_card_error({})\n", codegen::string(message)),
+        }
+    }
+}
+
+impl CardSource for Placeholder {
+    fn header_content(&self) -> Option<String> {
+        None
+    }
+    fn id(&self) -> String {
+        // The id of the card might be what's broken, it is put back on the compiled card
+        "placeholder".to_owned()
+    }
+    fn deck(&self) -> String {
+        self.deck.clone()
+    }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn question(&self) -> String {
+        self.content.clone()
+    }
+    fn answer(&self) -> String {
+        self.content.clone()
+    }
+    fn locations(&self) -> Vec<String> {
+        // Same thing as the id, the locations are the ones the card was loaded with anyways
+        Vec::new()
+    }
+    fn origin(&self) -> Option<CardOrigin> {
+        None
+    }
+    fn cloze(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Split the pages of a document into cards, using the metadata emitted by the `card` and
+/// `answer` functions of cards_internal.typ to know where each part of a card starts.
+fn split_cards(document: PagedDocument) -> Result<Vec<CompiledCard>, CoreError> {
+    enum Part {
+        Question(String),
+        Answer,
+    }
+
+    let label = Label::new(PicoStr::intern("_flashbang_part")).expect("label isn't empty");
+    let introspector = &document.introspector;
+
+    let parts = introspector
+        .query(&Selector::Label(label))
+        .iter()
+        .map(|content| {
+            let invalid = || CoreError::Other {
+                details: "invalid card metadata in compiled document".to_owned(),
+            };
+
+            let location = content.location().ok_or_else(invalid)?;
+            let Value::Dict(dict) = &content
+                .to_packed::<MetadataElem>()
+                .ok_or_else(invalid)?
+                .value
+            else {
+                return Err(invalid());
+            };
+
+            let part = match (dict.get("kind"), dict.get("id")) {
+                (Ok(Value::Str(kind)), Ok(Value::Str(id))) if kind.as_str() == "question" => {
+                    Part::Question(id.to_string())
+                }
+                (Ok(Value::Str(kind)), _) if kind.as_str() == "answer" => Part::Answer,
+                _ => return Err(invalid()),
+            };
+
+            Ok((introspector.page(location).get() - 1, part))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let pages = document
+        .pages
+        .into_iter()
+        .map(|page| Arc::new(CardPage(page)))
+        .collect_vec();
+    let mut cards = Vec::<CompiledCard>::new();
+
+    for (index, (start, part)) in parts.iter().enumerate() {
+        // A part spans until the next one starts
+        let end = parts
+            .get(index + 1)
+            .map(|(next, _)| *next)
+            .unwrap_or(pages.len())
+            .max(*start + 1)
+            .min(pages.len());
+        let part_pages = pages[(*start).min(end)..end].to_vec();
+
+        match part {
+            Part::Question(id) => cards.push(CompiledCard {
+                id: id.clone(),
+                question: part_pages,
+                answer: Vec::new(),
+                diagnostics: Vec::new(),
+            }),
+            Part::Answer => {
+                if let Some(card) = cards.last_mut() {
+                    card.answer = part_pages;
+                }
+            }
+        }
+    }
+
+    Ok(cards)
+}
+
+impl WorldState {
+    /// Add a file to the loaded files map
+    pub fn load_file(&self, slot: FileSlot) -> Option<FileSlot> {
+        self.files.lock().insert(slot.id, slot)
+    }
+
+    /// Get a file
+    pub fn get_file(&self, id: &FileId) -> Option<FileSlot> {
+        self.files.lock().get(id).cloned()
+    }
+}
+
+/// Newtype around typst::layout::Page because I need it to derive uniffi::Object
+#[derive(Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+pub struct CardPage(Page);
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+impl CardPage {
+    pub fn svg(&self) -> String {
+        typst_svg::svg(&self.0)
+    }
+}
+
+#[cfg(feature = "raster")]
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+impl CardPage {
+    /// Render to a png image, with an optional opaque background color (0xRRGGBB)
+    pub fn png(&self, pixel_per_pt: f32, background: Option<u32>) -> Result<Vec<u8>, CoreError> {
+        self.pixmap(pixel_per_pt, background)?
+            .encode_png()
+            .map_err(|err| CoreError::Other {
+                details: format!("couldn't encode png: {err}"),
+            })
+    }
+    /// Render to raw pixels, with an optional opaque background color (0xRRGGBB)
+    pub fn rgba(&self, pixel_per_pt: f32, background: Option<u32>) -> Result<Bitmap, CoreError> {
+        let pixmap = self.pixmap(pixel_per_pt, background)?;
+
+        Ok(Bitmap {
+            width: pixmap.width(),
+            height: pixmap.height(),
+            data: pixmap
+                .pixels()
+                .iter()
+                .flat_map(|pixel| {
+                    let color = pixel.demultiply();
+                    [color.red(), color.green(), color.blue(), color.alpha()]
+                })
+                .collect(),
+        })
+    }
+}
+
+impl CardPage {
+    #[cfg(feature = "pdf")]
+    pub(crate) fn frame(&self) -> &typst::layout::Frame {
+        &self.0.frame
+    }
+}
+
+#[cfg(feature = "raster")]
+impl CardPage {
+    /// Most pixels a page is rendered to, 64MiB of rgba
+    const MAX_PIXELS: f64 = 4096.0 * 4096.0;
+
+    fn pixmap(
+        &self,
+        pixel_per_pt: f32,
+        background: Option<u32>,
+    ) -> Result<tiny_skia::Pixmap, CoreError> {
+        if !pixel_per_pt.is_finite() || pixel_per_pt <= 0.0 {
+            return Err(CoreError::Other {
+                details: format!("invalid pixel per pt: {pixel_per_pt}"),
+            });
+        }
+        let size = self.0.frame.size() * pixel_per_pt as f64;
+        let (width, height) = (
+            size.x.to_pt().ceil().max(1.0),
+            size.y.to_pt().ceil().max(1.0),
+        );
+        if width * height > Self::MAX_PIXELS {
+            return Err(CoreError::Other {
+                details: format!("page is too large to render ({width}x{height} pixels)"),
+            });
+        }
+
+        let page = typst_render::render(&self.0, pixel_per_pt);
+        let Some(color) = background else {
+            return Ok(page);
+        };
+        let Some(mut pixmap) = tiny_skia::Pixmap::new(page.width(), page.height()) else {
+            return Ok(page);
+        };
+
+        let [_, r, g, b] = color.to_be_bytes();
+        pixmap.fill(tiny_skia::Color::from_rgba8(r, g, b, 255));
+        pixmap.draw_pixmap(
+            0,
+            0,
+            page.as_ref(),
+            &tiny_skia::PixmapPaint::default(),
+            tiny_skia::Transform::identity(),
+            None,
+        );
+
+        Ok(pixmap)
+    }
+}
+
+/// Raw pixels of a rendered page, in straight (not premultiplied) RGBA, row by row
+#[cfg(feature = "raster")]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+#[cfg_attr(
+    feature = "wasm",
+    wasm_bindgen::prelude::wasm_bindgen(getter_with_clone)
+)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl TypstWorld for Compilation<'_> {
+    fn library(&self) -> &LazyHash<Library> {
+        self.world.library()
+    }
+    fn book(&self) -> &LazyHash<FontBook> {
+        self.world.book()
+    }
+    fn main(&self) -> FileId {
+        self.main.id()
+    }
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main.id() {
+            Ok(self.main.clone())
+        } else {
+            self.check_deck(id)?;
+            self.world.source(id)
+        }
+    }
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        if id == self.main.id() {
+            Ok(Bytes::from_string(self.main.text().to_owned()))
+        } else {
+            self.check_deck(id)?;
+            self.world.file(id)
+        }
+    }
+    fn font(&self, index: usize) -> Option<Font> {
+        self.world.font(index)
+    }
+    fn today(&self, offset: Option<i64>) -> Option<typst::foundations::Datetime> {
+        self.world.today(offset)
+    }
+}
+
+impl TypstWorld for WorldState {
+    fn library(&self) -> &LazyHash<Library> {
+        &self.library
+    }
+    fn book(&self) -> &LazyHash<FontBook> {
+        &self.book
+    }
+    fn main(&self) -> FileId {
+        self.main
+    }
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if !is_deck_file(id) {
+            self.packages.get_package_source(id, &self)
+        } else if let Some(slot) = self.get_file(&id) {
+            slot.source()
+        } else {
+            Err(FileError::AccessDenied)
+        }
+    }
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        if !is_deck_file(id) {
+            self.packages.get_package_file(id, &self)
+        } else if let Some(slot) = self.get_file(&id) {
+            slot.bytes()
+        } else {
+            Err(FileError::AccessDenied)
         }
     }
     fn font(&self, index: usize) -> Option<Font> {
@@ -417,3 +2149,1048 @@ impl TypstWorld for WorldState {
         None
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    #[cfg(feature = "cache")]
+    use std::io::Cursor;
+
+    use super::*;
+
+    struct NoPackages;
+
+    impl PackageProvider for NoPackages {
+        fn get_package_source(&self, _id: FileId, _world: &WorldState) -> FileResult<Source> {
+            Err(FileError::AccessDenied)
+        }
+        fn get_package_file(&self, _id: FileId, _world: &WorldState) -> FileResult<Bytes> {
+            Err(FileError::AccessDenied)
+        }
+    }
+
+    /// Cache keeping the decks in memory
+    #[cfg(feature = "cache")]
+    #[derive(Default)]
+    struct MemoryCache {
+        decks: Mutex<HashMap<DeckKey, MemoryDeck>>,
+    }
+
+    #[cfg(feature = "cache")]
+    #[derive(Default)]
+    struct MemoryDeck {
+        sha: Option<String>,
+        tarball: Vec<u8>,
+    }
+
+    #[cfg(feature = "cache")]
+    impl CacheProvider for MemoryCache {
+        #[cfg(feature = "github")]
+        fn get_sha(&self, deck: &DeckKey) -> Result<String, CoreError> {
+            self.decks
+                .lock()
+                .get(deck)
+                .and_then(|deck| deck.sha.clone())
+                .ok_or_else(|| CoreError::Other {
+                    details: "not cached".to_owned(),
+                })
+        }
+        #[cfg(feature = "github")]
+        fn save_sha(&self, deck: &DeckKey, sha: String) -> Result<(), CoreError> {
+            self.decks.lock().entry(deck.clone()).or_default().sha = Some(sha);
+            Ok(())
+        }
+        fn get_tarball(&self, deck: &DeckKey) -> Result<Box<dyn Read>, CoreError> {
+            match self.decks.lock().get(deck) {
+                Some(deck) if !deck.tarball.is_empty() => {
+                    Ok(Box::new(Cursor::new(deck.tarball.clone())))
+                }
+                _ => Err(CoreError::Other {
+                    details: "not cached".to_owned(),
+                }),
+            }
+        }
+        fn save_tarball(&self, deck: &DeckKey, data: &mut dyn Read) -> Result<(), CoreError> {
+            let mut tarball = Vec::new();
+            data.read_to_end(&mut tarball)?;
+            self.decks.lock().entry(deck.clone()).or_default().tarball = tarball;
+            Ok(())
+        }
+        fn list(&self) -> Result<Vec<CachedDeck>, CoreError> {
+            Ok(self
+                .decks
+                .lock()
+                .iter()
+                .map(|(key, deck)| CachedDeck {
+                    key: key.clone(),
+                    sha: deck.sha.clone(),
+                    size: deck.tarball.len() as u64,
+                })
+                .collect())
+        }
+        fn evict(&self, deck: &DeckKey) -> Result<(), CoreError> {
+            self.decks.lock().remove(deck);
+            Ok(())
+        }
+    }
+
+    /// Gzipped tarball of the given files
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::fast(),
+        ));
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn world() -> WorldState {
+        WorldState::without_cache(NoPackages)
+    }
+
+    fn config() -> SourceConfig {
+        SourceConfig {
+            page_width: 200,
+            text_size: 12,
+            text_color: 0,
+            sans_math: false,
+        }
+    }
+
+    pub(crate) fn compile(content: &str) -> Result<CompileOutput, CoreError> {
+        let state = CardState::new();
+        let cards = state.parse(0, "deck.typ", content).unwrap();
+        let world = world();
+        world.prepare_source(&state, &cards, config())?;
+        world.compile()
+    }
+
+    #[test]
+    fn test_compile_cards() {
+        let output = compile(
+            "#card(\"a\", \"A\", ())\nQuestion\n#answer\nAnswer\n\
+             #card(\"b\", \"B\", ())\nFirst\n#pagebreak()\nSecond\n#answer\nAnswer\n",
+        )
+        .unwrap();
+
+        let pages = |card: &CompiledCard| (card.id.clone(), card.question.len(), card.answer.len());
+        assert_eq!(
+            output.cards.iter().map(pages).collect_vec(),
+            vec![("a".to_owned(), 1, 1), ("b".to_owned(), 2, 1)]
+        );
+    }
+
+    #[test]
+    fn test_compile_cloze() {
+        let output = compile(
+            "#cloze(\"d\", \"Derivative\", ())\nThe derivative of #c[$x^n$] is #c[$n x^(n-1)$]\n\
+             #card(\"a\", \"A\", ())\nThe derivative of #c[$x^n$] is #c[$n x^(n-1)$]\n\
+             #answer\nAnswer\n",
+        )
+        .unwrap();
+
+        let ids = output
+            .cards
+            .iter()
+            .map(|card| card.id.as_str())
+            .collect_vec();
+        assert_eq!(ids, ["d.c1", "d.c2", "a"]);
+        let [first, second, card] = &output.cards[..] else {
+            unreachable!()
+        };
+        let svg = |pages: &[Arc<CardPage>]| pages[0].svg();
+
+        // Each card hides its own deletion, and reveals it on the answer side
+        assert_ne!(svg(&first.question), svg(&second.question));
+        assert_ne!(svg(&first.question), svg(&first.answer));
+        assert_ne!(svg(&first.answer), svg(&second.answer));
+        // Deletions are shown as is outside of cloze cards
+        assert_ne!(svg(&card.question), svg(&first.question));
+        assert_ne!(svg(&card.question), svg(&second.question));
+    }
+
+    #[test]
+    fn test_compile_isolated() {
+        let content = "#card(\"a\", \"A\", ())\nQuestion\n#answer\nAnswer\n\
+             #card(\"b\", \"B\", ())\n#broken()\n#answer\nAnswer\n\
+             #card(\"c\", \"C\", ())\nQuestion\n#answer\n#let\n\
+             #card(\"d\", \"D\", ())\nQuestion\n#answer\nAnswer\n";
+        let state = CardState::new();
+        let cards = state.parse(0, "deck.typ", content).unwrap();
+        let output = world().compile_isolated(&state, &cards, config()).unwrap();
+
+        let failed = |card: &CompiledCard| {
+            (
+                card.id.clone(),
+                !card.diagnostics.is_empty(),
+                card.question.len(),
+            )
+        };
+        assert_eq!(
+            output.cards.iter().map(failed).collect_vec(),
+            vec![
+                ("a".to_owned(), false, 1),
+                ("b".to_owned(), true, 1),
+                ("c".to_owned(), true, 1),
+                ("d".to_owned(), false, 1),
+            ]
+        );
+        assert_eq!(output.cards[1].diagnostics[0].card_id.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_compile_parallel() {
+        let content = (0..10)
+            .map(|i| match i {
+                4 => format!("#card(\"{i}\", \"{i}\", ())\n#broken()\n#answer\nAnswer\n"),
+                _ => format!("#card(\"{i}\", \"{i}\", ())\nQuestion {i}\n#answer\nAnswer\n"),
+            })
+            .join("");
+        let state = CardState::new();
+        let cards = state.parse(0, "deck.typ", &content).unwrap();
+        let options = CompileOptions {
+            chunk_size: 3,
+            threads: 4,
+        };
+        let output = world()
+            .compile_parallel(&state, &cards, config(), options)
+            .unwrap();
+
+        let failed = |card: &CompiledCard| (card.id.clone(), !card.diagnostics.is_empty());
+        assert_eq!(
+            output.cards.iter().map(failed).collect_vec(),
+            (0..10).map(|i| (i.to_string(), i == 4)).collect_vec()
+        );
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let state = CardState::new();
+        let world = world();
+        let render = |content: &str| {
+            let cards = state.parse(0, "deck.typ", content).unwrap();
+            let output = world
+                .render(&state, &cards, config(), CompileOptions::default())
+                .unwrap();
+            output
+                .cards
+                .iter()
+                .map(|card| (card.id.clone(), card.cached))
+                .collect_vec()
+        };
+
+        let content = "#card(\"a\", \"A\", ())\nQuestion\n#answer\nAnswer\n\
+             #card(\"b\", \"B\", ())\nQuestion\n#answer\nAnswer\n";
+        assert_eq!(
+            render(content),
+            vec![("a".to_owned(), false), ("b".to_owned(), false)]
+        );
+        assert_eq!(
+            render(content),
+            vec![("a".to_owned(), true), ("b".to_owned(), true)]
+        );
+
+        let modified = "#card(\"a\", \"A\", ())\nQuestion\n#answer\nAnswer\n\
+             #card(\"b\", \"B\", ())\nOther question\n#answer\nAnswer\n";
+        assert_eq!(
+            render(modified),
+            vec![("a".to_owned(), true), ("b".to_owned(), false)]
+        );
+
+        // Only the files of the deck of the cards matter
+        let lib = |deck: &str, content: &str| {
+            let id = deck_file_id(deck, "lib.typ");
+            let source = Source::new(id, content.to_owned());
+            world.load_file(FileSlot::with_source(id, source));
+        };
+        lib("other", "#let x = 1");
+        assert_eq!(
+            render(modified),
+            vec![("a".to_owned(), true), ("b".to_owned(), true)]
+        );
+        lib("", "#let x = 1");
+        assert_eq!(
+            render(modified),
+            vec![("a".to_owned(), false), ("b".to_owned(), false)]
+        );
+
+        // Failed cards aren't cached
+        let broken = "#card(\"c\", \"C\", ())\n#broken()\n#answer\nAnswer\n";
+        assert_eq!(render(broken), vec![("c".to_owned(), false)]);
+        assert_eq!(render(broken), vec![("c".to_owned(), false)]);
+    }
+
+    #[cfg(feature = "raster")]
+    #[test]
+    fn test_raster() {
+        let output = compile("#card(\"a\", \"A\", ())\nQuestion\n#answer\nAnswer\n").unwrap();
+        let page = &output.cards[0].question[0];
+
+        let bitmap = page.rgba(2.0, Some(0x123456)).unwrap();
+        assert_eq!(bitmap.width, 400);
+        assert_eq!(
+            bitmap.data.len(),
+            (bitmap.width * bitmap.height * 4) as usize
+        );
+        assert_eq!(bitmap.data[..4], [0x12, 0x34, 0x56, 0xFF]);
+
+        let png = page.png(1.0, None).unwrap();
+        assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+
+        for pixel_per_pt in [0.0, -1.0, f32::NAN, f32::INFINITY, 1e6] {
+            assert!(page.rgba(pixel_per_pt, None).is_err(), "{pixel_per_pt}");
+            assert!(page.png(pixel_per_pt, Some(0)).is_err(), "{pixel_per_pt}");
+        }
+    }
+
+    #[test]
+    fn test_load_assets() {
+        let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\" height=\"10\"></svg>";
+        let tarball = tarball(&[
+            (
+                "repo/deck/cards.typ",
+                b"#card(\"a\", \"A\", ())\n#image(\"/img/x.svg\")\n#answer\n#read(\"/data.csv\")\n",
+            ),
+            ("repo/img/x.svg", svg.as_bytes()),
+            ("repo/data.csv", b"a,b\n1,2\n"),
+            ("repo/big.png", &[0; 256]),
+            ("repo/notes.md", b"# Notes"),
+        ]);
+
+        let state = CardState::new();
+        let world = world();
+        world.set_asset_limits(AssetLimits {
+            max_file_size: 128,
+            ..Default::default()
+        });
+        let result = world
+            .load_from_tarball(&state, tarball.as_slice(), &LoadOptions::default())
+            .unwrap();
+
+        assert_eq!(result.cards.len(), 1);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].path, "repo/big.png");
+        assert!(
+            world
+                .get_file(&FileId::new(None, VirtualPath::new("notes.md")))
+                .is_none()
+        );
+
+        let output = world
+            .compile_isolated(&state, &result.cards, config())
+            .unwrap();
+        assert!(output.cards[0].diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_load_error_span() {
+        let tarball = tarball(&[
+            ("repo/ok.typ", b"#card(\"a\", \"A\", ())\nQ\n#answer\nA\n"),
+            ("repo/broken.typ", b"Intro\n  #card(\"b\")\n#answer\n"),
+        ]);
+        let state = CardState::new();
+        let result = world()
+            .load_from_tarball(&state, tarball.as_slice(), &LoadOptions::default())
+            .unwrap();
+
+        assert_eq!(result.cards.len(), 1);
+        assert_eq!(result.errors.len(), 1);
+        let error = &result.errors[0];
+        assert_eq!(error.path(), "repo/broken.typ");
+        assert_eq!(error.span().unwrap().file, "broken.typ");
+        assert_eq!((error.line(), error.column()), (Some(2), Some(3)));
+    }
+
+    #[cfg(all(feature = "github", feature = "cache"))]
+    #[test]
+    fn test_load_stale() {
+        let state = CardState::new();
+        let error = || CoreError::HTTP {
+            details: "network is down".to_owned(),
+        };
+
+        let key = DeckKey::github("owner/repo", "main");
+        let cache = MemoryCache::default();
+        let data = tarball(&[("repo/a.typ", b"#card(\"a\", \"A\", ())\nQ\n#answer\nA\n")]);
+        cache.save_sha(&key, "abc".to_owned()).unwrap();
+        cache.save_tarball(&key, &mut data.as_slice()).unwrap();
+
+        let world = WorldState::new(NoPackages, cache);
+        let result = world
+            .load_stale(&state, "", &key, &LoadOptions::default(), error())
+            .unwrap();
+        assert_eq!(result.cards.len(), 1);
+
+        let stale = result.stale.unwrap();
+        assert_eq!(stale.sha, "abc");
+        assert!(stale.error.contains("network is down"));
+
+        // Nothing to fall back to
+        let other = DeckKey::github("owner/repo", "dev");
+        let result = world.load_stale(&state, "", &other, &LoadOptions::default(), error());
+        assert!(matches!(result, Err(CoreError::HTTP { .. })));
+        let world = WorldState::new(NoPackages, NoCache);
+        let result = world.load_stale(&state, "", &key, &LoadOptions::default(), error());
+        assert!(matches!(result, Err(CoreError::HTTP { .. })));
+    }
+
+    #[cfg(all(feature = "github", feature = "cache"))]
+    #[test]
+    fn test_load_from_remote() {
+        use crate::remote::{RefKind, stand_in::StandIn};
+
+        let card = |id: &str| format!("#card(\"{id}\", \"Name\", ())\nQ\n#answer\nA\n");
+        let server = StandIn::start();
+        let api = format!("{}/api/v3", server.url);
+        let remote = Github::enterprise(&api, "owner/repo".into(), "main".into(), None).unwrap();
+        let head = |sha: &str| format!(r#"{{"object":{{"sha":"{sha}","type":"commit"}}}}"#);
+        let commit = "/api/v3/repos/owner/repo/git/ref/heads/main";
+        server.route(commit, head("abc"));
+        server.route(
+            "/api/v3/repos/owner/repo/tarball/abc",
+            tarball(&[("repo/a.typ", card("a").as_bytes())]),
+        );
+
+        let options = LoadOptions::default();
+        let state = CardState::new();
+        let world = WorldState::new(NoPackages, MemoryCache::default());
+        let result = world.load_from_remote(&state, &remote, &options).unwrap();
+        assert_eq!(result.cards.len(), 1);
+        assert!(result.diff.is_none());
+        assert_eq!(result.reference.unwrap().kind, RefKind::Branch);
+        let key = DeckKey::new(remote.host(), "owner/repo", "main");
+        assert_eq!(world.cached_decks().unwrap()[0].key, key);
+
+        server.route(commit, head("def"));
+        server.route(
+            "/api/v3/repos/owner/repo/tarball/def",
+            tarball(&[("repo/a.typ", (card("a") + &card("b")).as_bytes())]),
+        );
+        let diff = world
+            .load_from_remote(&state, &remote, &options)
+            .unwrap()
+            .diff
+            .unwrap();
+        assert_eq!(
+            (diff.old_sha.as_str(), diff.new_sha.as_str()),
+            ("abc", "def")
+        );
+        assert_eq!(diff.added.len(), 1);
+
+        // The ref can't be resolved anymore, the cached deck is used
+        server.route(commit, "");
+        let result = world.load_from_remote(&state, &remote, &options).unwrap();
+        assert_eq!(result.cards.len(), 2);
+        assert_eq!(result.stale.unwrap().sha, "def");
+    }
+
+    #[cfg(all(feature = "github", feature = "cache"))]
+    #[test]
+    fn test_incremental_sync() {
+        use crate::remote::stand_in::StandIn;
+
+        let card = |id: &str| format!("#card(\"{id}\", \"Name\", ())\nQ\n#answer\nA\n");
+        let server = StandIn::start();
+        let api = format!("{}/api/v3", server.url);
+        let remote = Github::enterprise(&api, "owner/repo".into(), "main".into(), None).unwrap();
+        let repo = "/api/v3/repos/owner/repo";
+        let head = |sha: &str| format!(r#"{{"object":{{"sha":"{sha}","type":"commit"}}}}"#);
+        let commit = format!("{repo}/git/ref/heads/main");
+        server.route(&commit, head("abc"));
+        server.route(
+            &format!("{repo}/tarball/abc"),
+            tarball(&[
+                ("owner-repo-abc/a.typ", card("a").as_bytes()),
+                ("owner-repo-abc/img/logo.png", b"png"),
+            ]),
+        );
+
+        let options = LoadOptions::default();
+        let state = CardState::new();
+        let world = WorldState::new(NoPackages, MemoryCache::default());
+        world.load_from_remote(&state, &remote, &options).unwrap();
+
+        // Only the changed files are fetched, there is no tarball for this commit
+        server.route(&commit, head("def"));
+        server.route(
+            &format!("{repo}/compare/abc...def"),
+            r#"{"status":"ahead","files":[
+                {"filename":"a.typ","status":"removed","sha":"a0"},
+                {"filename":"cards/b.typ","status":"added","sha":"b1"}
+            ]}"#,
+        );
+        server.route(&format!("{repo}/git/blobs/b1"), card("b"));
+        let result = world.load_from_remote(&state, &remote, &options).unwrap();
+        assert!(result.stale.is_none());
+        assert_eq!(
+            result
+                .cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect_vec(),
+            ["b"]
+        );
+        let diff = result.diff.unwrap();
+        assert_eq!((diff.added.len(), diff.removed.len()), (1, 1));
+        assert!(
+            world
+                .get_file(&FileId::new(None, VirtualPath::new("img/logo.png")))
+                .is_some()
+        );
+
+        // History was rewritten, the whole tarball is downloaded
+        server.route(&commit, head("ghi"));
+        server.route(
+            &format!("{repo}/compare/def...ghi"),
+            r#"{"status":"diverged"}"#,
+        );
+        server.route(
+            &format!("{repo}/tarball/ghi"),
+            tarball(&[("owner-repo-ghi/c.typ", card("c").as_bytes())]),
+        );
+        let result = world.load_from_remote(&state, &remote, &options).unwrap();
+        assert_eq!(
+            result
+                .cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect_vec(),
+            ["c"]
+        );
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_cached_decks() {
+        let personal = DeckKey::github("me/notes", "main");
+        let course = DeckKey::new("gitlab.com", "school/course", "2025");
+        let cache = MemoryCache::default();
+        cache
+            .save_tarball(&personal, &mut [1; 10].as_slice())
+            .unwrap();
+        cache
+            .save_tarball(&course, &mut [2; 20].as_slice())
+            .unwrap();
+
+        let world = WorldState::new(NoPackages, cache);
+        let decks = world.cached_decks().unwrap();
+        assert_eq!(
+            decks
+                .iter()
+                .map(|deck| &deck.key)
+                .sorted_by_key(|key| &key.repo)
+                .collect_vec(),
+            [&personal, &course]
+        );
+        assert_eq!(world.cache_size().unwrap(), 30);
+
+        world.evict_deck(&personal).unwrap();
+        assert_eq!(world.cached_decks().unwrap()[0].key, course);
+        assert_eq!(world.cache_size().unwrap(), 20);
+    }
+
+    #[test]
+    fn test_load_decks() {
+        let deck = |greeting: &str, id: &str| DeckSource::Tarball {
+            data: tarball(&[
+                (
+                    "repo/cards.typ",
+                    format!(
+                        "//![FLASHBANG HEADER]\n#import \"/header.typ\": hi\n\
+                         #card(\"shared\", \"S\", ())\n#hi\n#answer\nA\n\
+                         #card(\"{id}\", \"U\", ())\n#hi\n#answer\nA\n"
+                    )
+                    .as_bytes(),
+                ),
+                (
+                    "repo/header.typ",
+                    format!("//![FLASHBANG INCLUDE]\n#let hi = [{greeting}]\n").as_bytes(),
+                ),
+            ]),
+        };
+        let decks = vec![
+            NamespacedDeck {
+                namespace: "course".to_owned(),
+                source: deck("Bonjour", "lecture"),
+                options: LoadOptions::default(),
+            },
+            NamespacedDeck {
+                namespace: String::new(),
+                source: deck("Hello", "note"),
+                options: LoadOptions::default(),
+            },
+        ];
+
+        let state = CardState::new();
+        let world = world();
+        let merged = world.load_decks(&state, decks.clone()).unwrap();
+
+        assert_eq!(
+            merged.collisions,
+            [IdCollision {
+                id: "shared".to_owned(),
+                decks: vec!["course".to_owned(), String::new()],
+            }]
+        );
+
+        let cards = merged
+            .decks
+            .into_iter()
+            .flat_map(|deck| deck.result.cards)
+            .collect_vec();
+        assert_eq!(
+            cards.iter().map(|card| card.id.as_str()).collect_vec(),
+            ["course:shared", "course:lecture", "shared", "note"]
+        );
+        assert_eq!(cards[0].deck, "course");
+
+        // Each card imports the header of its own deck, even when compiled together
+        let output = world.compile_isolated(&state, &cards, config()).unwrap();
+        assert!(output.cards.iter().all(|card| card.diagnostics.is_empty()));
+        assert_ne!(
+            output.cards[0].question[0].svg(),
+            output.cards[2].question[0].svg()
+        );
+        assert_eq!(
+            output.cards[0].question[0].svg(),
+            output.cards[1].question[0].svg()
+        );
+
+        world.prepare_source(&state, &cards, config()).unwrap();
+        assert_eq!(world.compile().unwrap().cards.len(), 4);
+
+        let mut invalid = decks.clone();
+        invalid[0].namespace = "my deck".to_owned();
+        assert!(world.load_decks(&state, invalid).is_err());
+        let mut duplicate = decks;
+        duplicate[1].namespace = "course".to_owned();
+        assert!(world.load_decks(&state, duplicate).is_err());
+    }
+
+    #[test]
+    fn test_deck_isolation() {
+        let lib = "//![FLASHBANG INCLUDE]\n#let secret = [Secret]\n";
+        // Makes lib.typ the entrypoint of deck b, when imported as a package
+        let manifest = "[package]\nname = \"b\"\nversion = \"0.0.0\"\nentrypoint = \"lib.typ\"\n";
+        let cards = |id: &str, import: &str| {
+            format!(
+                "//![FLASHBANG HEADER]\n#import \"{import}\": secret\n\
+                 #card(\"{id}\", \"C\", ())\n#secret\n#answer\nA\n"
+            )
+        };
+        let other = cards("other", "@flashbang-deck/b:0.0.0");
+        let own = cards("own", "/lib.typ");
+        let deck = |namespace: &str, files: &[(&str, &[u8])]| NamespacedDeck {
+            namespace: namespace.to_owned(),
+            source: DeckSource::Tarball {
+                data: tarball(files),
+            },
+            options: LoadOptions::default(),
+        };
+        let decks = vec![
+            deck("a", &[("repo/cards.typ", other.as_bytes())]),
+            deck(
+                "b",
+                &[
+                    ("repo/cards.typ", own.as_bytes()),
+                    ("repo/lib.typ", lib.as_bytes()),
+                    ("repo/typst.toml", manifest.as_bytes()),
+                ],
+            ),
+            deck("", &[("repo/cards.typ", other.as_bytes())]),
+        ];
+
+        let state = CardState::new();
+        let world = world();
+        let merged = world.load_decks(&state, decks).unwrap();
+        let cards = merged
+            .decks
+            .into_iter()
+            .flat_map(|deck| deck.result.cards)
+            .collect_vec();
+
+        // Only deck b can import its own lib.typ
+        let output = world.compile_isolated(&state, &cards, config()).unwrap();
+        let failed = output
+            .cards
+            .iter()
+            .map(|card| (card.id.as_str(), !card.diagnostics.is_empty()))
+            .collect_vec();
+        assert_eq!(
+            failed,
+            [("a:other", true), ("b:own", false), ("other", true)]
+        );
+        let message = &output.cards[0].diagnostics[0].message;
+        assert!(message.contains("not found"), "{message}");
+    }
+
+    #[cfg(all(feature = "github", feature = "cache"))]
+    #[test]
+    fn test_tarball_cards() {
+        let tarball = tarball(&[
+            ("repo/a.typ", b"#card(\"a\", \"A\", ())\nQ\n#answer\nA\n"),
+            ("repo/lib.typ", b"//![FLASHBANG INCLUDE]\n#let hi = [Hi]\n"),
+            ("repo/data.csv", b"a,b\n1,2\n"),
+        ]);
+
+        // The previous revision is only parsed, it mustn't shadow the files of the new one
+        let options = LoadOptions::default();
+        let world = world();
+        let cards = world.tarball_cards(&CardState::new(), "", tarball.as_slice(), &options);
+        assert_eq!(cards.len(), 1);
+        assert!(
+            world
+                .get_file(&FileId::new(None, VirtualPath::new("lib.typ")))
+                .is_none()
+        );
+        assert!(
+            world
+                .get_file(&FileId::new(None, VirtualPath::new("data.csv")))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_load_from_directory() {
+        let root = std::env::temp_dir().join(format!("fb-core-deck-{}", std::process::id()));
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+
+        write(
+            "deck/cards.typ",
+            "//![FLASHBANG HEADER]\n#import \"/lib.typ\": hi\n\
+             #card(\"a\", \"A\", ())\n#hi #image(\"/img/x.svg\")\n#answer\nAnswer\n",
+        );
+        write("lib.typ", "//![FLASHBANG INCLUDE]\n#let hi = [Hi]\n");
+        write(
+            "ignored.typ",
+            "//![FLASHBANG TODO]\n//![FLASHBANG IGNORE]\n#card(\"b\", \"B\", ())\nQ\n#answer\nA\n",
+        );
+        write(
+            "drafts/draft.typ",
+            "#card(\"c\", \"C\", ())\nQ\n#answer\nA\n",
+        );
+        write(
+            "img/x.svg",
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\" height=\"10\"></svg>",
+        );
+
+        let state = CardState::new();
+        let world = world();
+        let options = LoadOptions {
+            exclude: vec!["drafts".to_owned()],
+            ..Default::default()
+        };
+        let result = world.load_from_directory(&state, &root, &options);
+        // Only the deck root is walked, with the files it needs allowed from elsewhere
+        let rooted = world_with_root(&root, "deck", &["lib.typ", "img"]);
+        std::fs::remove_dir_all(&root).unwrap();
+        let result = result.unwrap();
+        assert_eq!(rooted, ["a"]);
+
+        assert!(result.errors.is_empty());
+        assert_eq!(
+            result
+                .cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect_vec(),
+            ["a"]
+        );
+        assert_eq!(result.cards[0].origin.question.file, "deck/cards.typ");
+        assert_eq!(result.warnings.len(), 1);
+        assert_eq!(result.warnings[0].span.file, "ignored.typ");
+        assert_eq!(result.includes, ["lib.typ"]);
+
+        let output = world
+            .compile_isolated(&state, &result.cards, config())
+            .unwrap();
+        assert!(output.cards[0].diagnostics.is_empty());
+
+        let options = LoadOptions {
+            exclude: vec!["[".to_owned()],
+            ..Default::default()
+        };
+        assert!(
+            world
+                .load_from_directory(&state, "/nonexistent", &options)
+                .is_err()
+        );
+    }
+
+    /// Ids of the cards of a directory loaded with a deck root, which must compile
+    fn world_with_root(path: &Path, root: &str, external: &[&str]) -> Vec<String> {
+        let state = CardState::new();
+        let world = world();
+        let options = LoadOptions {
+            root: root.to_owned(),
+            external: external.iter().map(|path| path.to_string()).collect(),
+            ..Default::default()
+        };
+        let result = world.load_from_directory(&state, path, &options).unwrap();
+        assert!(result.errors.is_empty());
+
+        let output = world
+            .compile_isolated(&state, &result.cards, config())
+            .unwrap();
+        assert!(output.cards.iter().all(|card| card.diagnostics.is_empty()));
+        result.cards.into_iter().map(|card| card.id).collect()
+    }
+
+    #[test]
+    fn test_deck_root() {
+        let card = |id: &str, import: &str| {
+            format!(
+                "//![FLASHBANG HEADER]\n#import \"{import}\": hi\n\
+                 #card(\"{id}\", \"{id}\", ())\n#hi\n#answer\nA\n"
+            )
+        };
+        let tarball = tarball(&[
+            (
+                "repo/decks/la/cards.typ",
+                card("la", "/shared/lib.typ").as_bytes(),
+            ),
+            (
+                "repo/decks/la/lib.typ",
+                b"//![FLASHBANG INCLUDE]\n#let hi = [Hi]\n",
+            ),
+            (
+                "repo/decks/other/cards.typ",
+                card("other", "/shared/lib.typ").as_bytes(),
+            ),
+            (
+                "repo/shared/lib.typ",
+                b"//![FLASHBANG INCLUDE]\n#let hi = [Hi]\n",
+            ),
+            (
+                "repo/shared/cards.typ",
+                card("shared", "/shared/lib.typ").as_bytes(),
+            ),
+            (
+                "repo/notes/notes.typ",
+                card("notes", "/notes/lib.typ").as_bytes(),
+            ),
+            (
+                "repo/notes/lib.typ",
+                b"//![FLASHBANG INCLUDE]\n#let hi = [Hi]\n",
+            ),
+        ]);
+
+        let state = CardState::new();
+        let world = world();
+        let options = LoadOptions {
+            root: "/decks/la/".to_owned(),
+            external: vec!["shared".to_owned()],
+            ..Default::default()
+        };
+        let result = world
+            .load_from_tarball(&state, tarball.as_slice(), &options)
+            .unwrap();
+        assert_eq!(
+            result
+                .cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect_vec(),
+            ["la"]
+        );
+        assert_eq!(result.cards[0].origin.question.file, "decks/la/cards.typ");
+
+        let output = world
+            .compile_isolated(&state, &result.cards, config())
+            .unwrap();
+        assert!(output.cards[0].diagnostics.is_empty());
+        // Files outside of the root that aren't allowed are left out
+        assert!(
+            world
+                .get_file(&FileId::new(None, VirtualPath::new("notes/lib.typ")))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        let card = |id: &str| format!("#card(\"{id}\", \"{id}\", ())\nQ\n#answer\nA\n");
+        let manifest = r#"
+            name = "Linear algebra"
+            cards = ["chapters"]
+            exclude = ["**/drafts"]
+            assets = ["img"]
+            packages = ["@preview/cetz:0.4.0"]
+
+            [config]
+            text-size = 14
+        "#;
+        // The manifest comes after the files it applies to
+        let files = [
+            ("repo/la/chapters/one.typ", card("one")),
+            ("repo/la/chapters/drafts/two.typ", card("two")),
+            ("repo/la/readme.typ", card("readme")),
+            ("repo/la/img/a.svg", "<svg/>".to_owned()),
+            ("repo/la/b.svg", "<svg/>".to_owned()),
+            ("repo/la/flashbang.toml", manifest.to_owned()),
+        ];
+        let files = files
+            .iter()
+            .map(|(path, data)| (*path, data.as_bytes()))
+            .collect_vec();
+        let data = tarball(&files);
+
+        let state = CardState::new();
+        let world = world();
+        let options = LoadOptions {
+            root: "la".to_owned(),
+            ..Default::default()
+        };
+        let result = world
+            .load_from_tarball(&state, data.as_slice(), &options)
+            .unwrap();
+        assert_eq!(
+            result
+                .cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect_vec(),
+            ["one"]
+        );
+
+        let manifest = result.manifest.unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("Linear algebra"));
+        assert_eq!(manifest.config.apply(config()).text_size, 14);
+        // The package couldn't be fetched
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].to_string().contains("@preview/cetz:0.4.0"));
+
+        let file = |path: &str| world.get_file(&FileId::new(None, VirtualPath::new(path)));
+        assert!(file("la/img/a.svg").is_some());
+        assert!(file("la/b.svg").is_none());
+
+        // Files after the manifest are filtered as they come
+        let mut files = files;
+        files.rotate_right(1);
+        let data = tarball(&files);
+        let result = world
+            .load_from_tarball(&state, data.as_slice(), &options)
+            .unwrap();
+        assert_eq!(
+            result
+                .cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect_vec(),
+            ["one"]
+        );
+
+        // Decks needing a newer flashbang aren't loaded
+        let manifest = "min-flashbang-version = \"999.0.0\"";
+        let data = tarball(&[("repo/flashbang.toml", manifest.as_bytes())]);
+        let options = LoadOptions::default();
+        assert!(
+            world
+                .load_from_tarball(&state, data.as_slice(), &options)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_compile_diagnostics() {
+        let Err(CoreError::Typst { diagnostics }) = compile(
+            "#card(\"a\", \"A\", ())\nQuestion\n#answer\nAnswer\n\
+             #card(\"b\", \"B\", ())\nQuestion\n#answer\n\n  #not_a_function()\n",
+        ) else {
+            panic!("expected a typst error");
+        };
+
+        let error = &diagnostics[0];
+        assert_eq!(error.severity, Severity::Error);
+        assert_eq!(error.card_id.as_deref(), Some("b"));
+        assert_eq!(error.file_id.as_deref(), Some("/_main.typ"));
+
+        let location = error.location.as_ref().unwrap();
+        assert_eq!(location.file, "deck.typ");
+        assert_eq!((location.line, location.column), (9, 4));
+    }
+
+    /// The single error of a deck that fails to compile
+    fn compile_error(content: &str) -> Diagnostic {
+        match compile(content) {
+            Err(CoreError::Typst { diagnostics }) if diagnostics.len() == 1 => {
+                diagnostics.into_iter().next().unwrap()
+            }
+            Err(err) => panic!("expected a single typst error, got {err}"),
+            Ok(_) => panic!("expected a typst error"),
+        }
+    }
+
+    #[test]
+    fn test_compile_hints() {
+        let error = compile_error("#card(\"a\", \"A\", ())\nQ\n#answer\n$ab$\n");
+
+        assert_eq!(error.severity, Severity::Error);
+        assert_eq!(error.message, "unknown variable: ab");
+        assert_eq!(error.card_id.as_deref(), Some("a"));
+        assert_eq!(error.hints.len(), 2);
+        assert!(error.hints[0].contains("`a b`"));
+        assert!(error.trace.is_empty());
+
+        let location = error.location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (4, 2));
+        assert!(
+            error
+                .to_string()
+                .starts_with("deck.typ:4:2: error: unknown variable: ab")
+        );
+        assert!(error.to_string().contains("(hint: if you meant"));
+    }
+
+    #[test]
+    fn test_compile_trace() {
+        let error = compile_error(
+            "#card(\"a\", \"A\", ())\nQ\n#answer\nA\n\
+             #card(\"b\", \"B\", ())\n#let f() = panic(\"boom\")\n#f()\n#answer\nA\n",
+        );
+
+        assert_eq!(error.message, "panicked with: \"boom\"");
+        assert_eq!(error.card_id.as_deref(), Some("b"));
+        let location = error.location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (6, 12));
+
+        // The trace leads back to the call of the function
+        assert_eq!(error.trace.len(), 1);
+        assert!(error.trace[0].message.contains("call of function `f`"));
+        let call = error.trace[0].location.as_ref().unwrap();
+        assert_eq!(
+            (call.file.as_str(), call.line, call.column),
+            ("deck.typ", 7, 2)
+        );
+    }
+
+    #[test]
+    fn test_compile_warnings() {
+        let output =
+            compile("#card(\"a\", \"A\", ())\n#set text(font: \"NoSuchFont\")\nQ\n#answer\nA\n")
+                .unwrap();
+        assert_eq!(output.cards.len(), 1);
+
+        let [warning] = output.warnings.as_slice() else {
+            panic!("expected a single warning, got {:?}", output.warnings);
+        };
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.message, "unknown font family: nosuchfont");
+        assert_eq!(warning.card_id.as_deref(), Some("a"));
+        assert_eq!(warning.file_id.as_deref(), Some("/_main.typ"));
+        let location = warning.location.as_ref().unwrap();
+        assert_eq!(
+            (location.file.as_str(), location.line, location.column),
+            ("deck.typ", 2, 17)
+        );
+    }
+}
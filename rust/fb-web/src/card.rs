@@ -1,4 +1,4 @@
-use fb_core::cards::CardSource;
+use fb_core::cards::{CardOrigin, CardSource};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -9,6 +9,8 @@ pub struct Card {
     question: String,
     answer: String,
     locations: Vec<String>,
+    deck: String,
+    cloze: Option<u32>,
 }
 
 #[wasm_bindgen]
@@ -21,6 +23,7 @@ impl Card {
         question: String,
         answer: String,
         locations: Vec<String>,
+        deck: Option<String>,
     ) -> Self {
         Self {
             id,
@@ -29,8 +32,16 @@ impl Card {
             locations,
             header,
             name,
+            deck: deck.unwrap_or_default(),
+            cloze: None,
         }
     }
+
+    /// Deletion group hidden by the card, for cards generated from a `#cloze`
+    #[wasm_bindgen(setter)]
+    pub fn set_cloze(&mut self, cloze: Option<u32>) {
+        self.cloze = cloze;
+    }
 }
 
 impl CardSource for Card {
@@ -40,6 +51,9 @@ impl CardSource for Card {
     fn id(&self) -> String {
         self.id.clone()
     }
+    fn deck(&self) -> String {
+        self.deck.clone()
+    }
     fn name(&self) -> String {
         self.name.clone()
     }
@@ -52,4 +66,10 @@ impl CardSource for Card {
     fn locations(&self) -> Vec<String> {
         self.locations.clone()
     }
+    fn origin(&self) -> Option<CardOrigin> {
+        None
+    }
+    fn cloze(&self) -> Option<u32> {
+        self.cloze
+    }
 }
@@ -6,9 +6,11 @@ use std::{
 
 use fb_core::{
     cards::{CardState, SourceConfig},
+    diagnostics::{Diagnostic, Severity},
     error::{AsCoreError, CoreError},
+    render::RenderOutput,
     typst::syntax::{FileId, Source, VirtualPath},
-    world::{CardPage, FileSlot, WorldState},
+    world::{CardPage, CompileOptions, CompiledCard, FileSlot, WorldState},
 };
 use wasm_bindgen::prelude::*;
 use zip::ZipArchive;
@@ -31,6 +33,60 @@ impl<T, E: Error> ToJsError<T> for Result<T, E> {
     }
 }
 
+/// Result of a compilation
+#[wasm_bindgen]
+pub struct Compiled {
+    cards: Vec<CompiledCard>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[wasm_bindgen]
+impl Compiled {
+    #[wasm_bindgen(getter)]
+    pub fn card_count(&self) -> usize {
+        self.cards.len()
+    }
+
+    pub fn card_id(&self, index: usize) -> Option<String> {
+        Some(self.cards.get(index)?.id.clone())
+    }
+
+    pub fn question(&self, index: usize) -> Vec<CardPage> {
+        self.cards
+            .get(index)
+            .map(|card| card.question.iter().map(|page| (**page).clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn answer(&self, index: usize) -> Vec<CardPage> {
+        self.cards
+            .get(index)
+            .map(|card| card.answer.iter().map(|page| (**page).clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Errors of a card that failed to compile, its pages are then an error placeholder
+    pub fn card_diagnostics(&self, index: usize) -> Vec<Diagnostic> {
+        self.cards
+            .get(index)
+            .map(|card| card.diagnostics.clone())
+            .unwrap_or_default()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
+
+    /// Whether the compilation failed
+    #[wasm_bindgen(getter)]
+    pub fn failed(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+}
+
 #[wasm_bindgen]
 pub struct Core {
     card: CardState,
@@ -78,7 +134,7 @@ impl Core {
     pub fn new(data: Vec<u8>) -> Result<Self, JsError> {
         let data: Arc<[u8]> = data.into();
         let package_provider = ZippedPackageProvider::new(data.clone()).to_js()?;
-        let world = WorldState::new(package_provider);
+        let world = WorldState::without_cache(package_provider);
 
         provide_repository_files(&world, data).to_js()?;
 
@@ -93,8 +149,52 @@ impl Core {
         self.world.prepare_source(&self.card, cards, config).to_js()
     }
 
-    pub fn compile(&self) -> Result<Vec<CardPage>, JsError> {
-        self.world.compile().to_js()
+    /// Compile the prepared source, typst errors are reported through the diagnostics of the
+    /// result (with no cards) rather than thrown.
+    pub fn compile(&self) -> Result<Compiled, JsError> {
+        match self.world.compile() {
+            Ok(output) => Ok(Compiled {
+                cards: output.cards,
+                diagnostics: output.warnings,
+            }),
+            Err(CoreError::Typst { diagnostics }) => Ok(Compiled {
+                cards: Vec::new(),
+                diagnostics,
+            }),
+            Err(err) => Err(JsError::new(&err.to_string())),
+        }
+    }
+
+    /// Prepare and compile cards, replacing the ones that fail to compile with an error
+    /// placeholder so that the others can still be rendered.
+    pub fn compile_isolated(
+        &self,
+        cards: Vec<Card>,
+        config: SourceConfig,
+    ) -> Result<Compiled, JsError> {
+        let output = self
+            .world
+            .compile_isolated(&self.card, &cards, config)
+            .to_js()?;
+
+        Ok(Compiled {
+            cards: output.cards,
+            diagnostics: output.warnings,
+        })
+    }
+
+    /// Render cards to svg, reusing the cards that were already rendered with the same
+    /// content and config.
+    pub fn render(&self, cards: Vec<Card>, config: SourceConfig) -> Result<RenderOutput, JsError> {
+        // No threads on the web
+        let options = CompileOptions {
+            threads: 1,
+            ..Default::default()
+        };
+
+        self.world
+            .render(&self.card, &cards, config, options)
+            .to_js()
     }
 }
 